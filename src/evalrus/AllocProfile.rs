@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+use crate::evalrus::Traits::MutatorScope;
+use crate::evalrus::TypeList::TypeList;
+use crate::frontend::Token::SourcePos;
+
+/// Where an allocation was made: the names of the functions on the call stack, outermost first,
+/// and the source position of the allocating instruction if known. Positions are only known for
+/// bytecode emitted with `ByteCode::push_at()`, which nothing emits yet.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct AllocSite {
+    pub frames: Vec<String>,
+    pub pos: Option<SourcePos>,
+}
+
+impl AllocSite {
+    /// A site for allocations made by the host rather than by running code
+    pub fn host() -> AllocSite {
+        AllocSite {
+            frames: Vec::new(),
+            pos: None,
+        }
+    }
+
+    /// The innermost function name with the source position appended, e.g. `fib@3:7`
+    fn leaf(&self) -> String {
+        let name = self.frames.last().map(|name| name.as_str()).unwrap_or("<host>");
+
+        match self.pos {
+            Some(pos) => format!("{}@{}:{}", name, pos.line, pos.column),
+            None => String::from(name),
+        }
+    }
+}
+
+/// Something that knows which code is running, such as a VM Thread, registered with the heap so
+/// that the allocation profiler can attribute allocations
+pub trait CallStack {
+    fn alloc_site(&self, guard: &dyn MutatorScope) -> AllocSite;
+}
+
+/// Allocation counts for one site and object type
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct SiteStats {
+    pub objects: usize,
+    pub bytes: usize,
+}
+
+/// Objects and bytes allocated, aggregated by site and object type
+#[derive(Clone, Debug, Default)]
+pub struct AllocProfile {
+    sites: HashMap<(AllocSite, TypeList), SiteStats>,
+}
+
+impl AllocProfile {
+    pub fn new() -> AllocProfile {
+        AllocProfile::default()
+    }
+
+    /// Count an allocation of `bytes` bytes of the given type at the given site
+    pub fn record(&mut self, site: AllocSite, type_id: TypeList, bytes: usize) {
+        let stats = self.sites.entry((site, type_id)).or_default();
+        stats.objects += 1;
+        stats.bytes += bytes;
+    }
+
+    /// Every site and type allocated from, most bytes first
+    pub fn sites(&self) -> Vec<(&AllocSite, TypeList, SiteStats)> {
+        let mut sites: Vec<_> = self
+            .sites
+            .iter()
+            .map(|((site, type_id), stats)| (site, *type_id, *stats))
+            .collect();
+
+        sites.sort_by(|a, b| b.2.bytes.cmp(&a.2.bytes));
+        sites
+    }
+
+    /// Write the profile in the folded-stack format read by flamegraph tools: one line per site
+    /// and type, with the frames and the type separated by semicolons, followed by the bytes
+    /// allocated
+    pub fn write_folded(&self, out: &mut dyn Write) -> io::Result<()> {
+        for (site, type_id, stats) in self.sites() {
+            let mut frames: Vec<String> = site.frames.clone();
+            frames.pop();
+            frames.push(site.leaf());
+
+            writeln!(out, "{};{:?} {}", frames.join(";"), type_id, stats.bytes)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for AllocProfile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{:>12} {:>10}  {:<20} site", "bytes", "objects", "type")?;
+
+        for (site, type_id, stats) in self.sites() {
+            writeln!(
+                f,
+                "{:>12} {:>10}  {:<20} {}",
+                stats.bytes,
+                stats.objects,
+                format!("{:?}", type_id),
+                site.leaf()
+            )?;
+        }
+
+        Ok(())
+    }
+}
@@ -28,6 +28,8 @@ impl AllocHeader for ArenaHeader {
 
     fn mark(&mut self) {}
 
+    fn unmark(&mut self) {}
+
     fn is_marked(&self) -> bool {
         true
     }
@@ -1,4 +1,5 @@
 use crate::evalrus::Ptrs::{ScopedPtr, ScopedRef, TaggedPtr};
+use crate::evalrus::Trace::{Trace, Tracer};
 use crate::evalrus::Traits::MutatorScope;
 use crate::internals::Alloc::RawPtr;
 use crate::evalrus::TypeList::TypeList::*;
@@ -9,7 +10,11 @@ pub enum FatPtr {
     ArrayU8(RawPtr<ArrayU8>),
     ArrayU16(RawPtr<ArrayU16>),
     ArrayU32(RawPtr<ArrayU32>),
+    Boolean(bool),
+    Builtin(RawPtr<Builtin>),
+    Char(char),
     Dict(RawPtr<Dict>),
+    Float(RawPtr<Float>),
     Function(RawPtr<Function>),
     List(RawPtr<List>),
     Nil,
@@ -17,9 +22,11 @@ pub enum FatPtr {
     NumberObject(RawPtr<NumberObject>),
     Pair(RawPtr<Pair>),
     Partial(RawPtr<Partial>),
+    Resource(RawPtr<Resource>),
     Symbol(RawPtr<Symbol>),
     Text(RawPtr<Text>),
     Upvalue(RawPtr<Upvalue>),
+    WeakRef(RawPtr<WeakRef>),
 }
 impl FatPtr {
     pub fn as_value<'guard>(&self, guard: &'guard dyn MutatorScope) -> Value<'guard> {
@@ -33,7 +40,15 @@ impl FatPtr {
             FatPtr::ArrayU32(raw_ptr) => {
                 Value::ArrayU32(ScopedPtr::new(guard, raw_ptr.scoped_ref(guard)))
             }
+            FatPtr::Boolean(value) => Value::Boolean(*value),
+            FatPtr::Builtin(raw_ptr) => {
+                Value::Builtin(ScopedPtr::new(guard, raw_ptr.scoped_ref(guard)))
+            }
+            FatPtr::Char(value) => Value::Char(*value),
             FatPtr::Dict(raw_ptr) => Value::Dict(ScopedPtr::new(guard, raw_ptr.scoped_ref(guard))),
+            FatPtr::Float(raw_ptr) => {
+                Value::Float(ScopedPtr::new(guard, raw_ptr.scoped_ref(guard)))
+            }
             FatPtr::Function(raw_ptr) => {
                 Value::Function(ScopedPtr::new(guard, raw_ptr.scoped_ref(guard)))
             }
@@ -47,6 +62,9 @@ impl FatPtr {
             FatPtr::Partial(raw_ptr) => {
                 Value::Partial(ScopedPtr::new(guard, raw_ptr.scoped_ref(guard)))
             }
+            FatPtr::Resource(raw_ptr) => {
+                Value::Resource(ScopedPtr::new(guard, raw_ptr.scoped_ref(guard)))
+            }
             FatPtr::Symbol(raw_ptr) => {
                 Value::Symbol(ScopedPtr::new(guard, raw_ptr.scoped_ref(guard)))
             }
@@ -54,6 +72,42 @@ impl FatPtr {
             FatPtr::Upvalue(raw_ptr) => {
                 Value::Upvalue(ScopedPtr::new(guard, raw_ptr.scoped_ref(guard)))
             }
+            FatPtr::WeakRef(raw_ptr) => {
+                Value::WeakRef(ScopedPtr::new(guard, raw_ptr.scoped_ref(guard)))
+            }
+        }
+    }
+}
+
+/// Scan the fields of the object the FatPtr points at. The object itself must already have
+/// been marked.
+impl Trace for FatPtr {
+    fn trace(&self, tracer: &mut Tracer) {
+        unsafe {
+            match self {
+                FatPtr::ArrayU8(raw_ptr) => (*raw_ptr.as_ptr()).trace(tracer),
+                FatPtr::ArrayU16(raw_ptr) => (*raw_ptr.as_ptr()).trace(tracer),
+                FatPtr::ArrayU32(raw_ptr) => (*raw_ptr.as_ptr()).trace(tracer),
+                FatPtr::Builtin(raw_ptr) => (*raw_ptr.as_ptr()).trace(tracer),
+                FatPtr::Dict(raw_ptr) => (*raw_ptr.as_ptr()).trace(tracer),
+                FatPtr::Function(raw_ptr) => (*raw_ptr.as_ptr()).trace(tracer),
+                FatPtr::List(raw_ptr) => (*raw_ptr.as_ptr()).trace(tracer),
+                FatPtr::NumberObject(raw_ptr) => (*raw_ptr.as_ptr()).trace(tracer),
+                FatPtr::Pair(raw_ptr) => (*raw_ptr.as_ptr()).trace(tracer),
+                FatPtr::Partial(raw_ptr) => (*raw_ptr.as_ptr()).trace(tracer),
+                FatPtr::Resource(raw_ptr) => (*raw_ptr.as_ptr()).trace(tracer),
+                FatPtr::Text(raw_ptr) => (*raw_ptr.as_ptr()).trace(tracer),
+                FatPtr::Upvalue(raw_ptr) => (*raw_ptr.as_ptr()).trace(tracer),
+                FatPtr::WeakRef(raw_ptr) => (*raw_ptr.as_ptr()).trace(tracer),
+
+                // Leaf types hold no pointers
+                FatPtr::Boolean(_)
+                | FatPtr::Char(_)
+                | FatPtr::Float(_)
+                | FatPtr::Nil
+                | FatPtr::Number(_)
+                | FatPtr::Symbol(_) => (),
+            }
         }
     }
 }
@@ -0,0 +1,132 @@
+use std::cell::RefCell;
+use std::env;
+use std::rc::Rc;
+use std::time::Duration;
+use crate::evalrus::HeapStats::HeapStats;
+
+/// Environment variable enabling the built-in GC logger. Any value other than `0` logs
+/// collections and out-of-memory failures to stderr; `all` also logs block acquire and release.
+pub const GC_LOG_ENV: &str = "EVALRUS_GC_LOG";
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CollectionKind {
+    Minor,
+    Full,
+    /// A full collection marked in steps between safepoints
+    Incremental,
+}
+
+/// Something of interest the heap did, passed to every registered hook
+#[derive(Clone, Debug)]
+pub enum GcEvent {
+    CollectionStart {
+        kind: CollectionKind,
+        /// Bytes held in blocks and large objects
+        heap_bytes: usize,
+        /// Bytes allocated since the previous collection
+        allocated_bytes: usize,
+    },
+    CollectionEnd {
+        kind: CollectionKind,
+        /// How long the mutator was stopped for; for an incremental collection, the final pause
+        pause: Duration,
+        /// Bytes held in blocks and large objects when the collection started
+        heap_bytes_before: usize,
+        stats: HeapStats,
+    },
+    /// Fresh blocks were taken from the block source
+    BlocksAcquired { count: usize, blocks_in_use: usize },
+    /// Free blocks were returned to the block source
+    BlocksReleased { count: usize, blocks_in_use: usize },
+    /// An allocation failed even after a collection
+    OutOfMemory { requested_bytes: usize, stats: HeapStats },
+}
+
+/// A callback registered with `Memory::on_gc_event()`
+pub type GcHook = Box<dyn Fn(&GcEvent)>;
+
+/// The hooks registered with a heap, shared with the heap's block list so that block turnover
+/// is reported as it happens
+pub struct GcHooks {
+    hooks: RefCell<Vec<Rc<dyn Fn(&GcEvent)>>>,
+}
+
+impl GcHooks {
+    /// Start with the built-in logger if it is enabled
+    pub fn from_env() -> GcHooks {
+        GcHooks {
+            hooks: RefCell::new(logger_from_env().map(Rc::from).into_iter().collect()),
+        }
+    }
+
+    pub fn add(&self, hook: GcHook) {
+        self.hooks.borrow_mut().push(Rc::from(hook));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.borrow().is_empty()
+    }
+
+    /// Pass an event to every registered hook. The event is only built if there are any. Hooks
+    /// are called from a snapshot so that a hook may register another, which is first called
+    /// for the next event.
+    pub fn emit<F: FnOnce() -> GcEvent>(&self, event: F) {
+        let hooks: Vec<_> = self.hooks.borrow().iter().cloned().collect();
+
+        if !hooks.is_empty() {
+            let event = event();
+            for hook in hooks {
+                hook(&event);
+            }
+        }
+    }
+}
+
+/// Return the built-in stderr logger if enabled by the `EVALRUS_GC_LOG` environment variable
+pub fn logger_from_env() -> Option<GcHook> {
+    match env::var(GC_LOG_ENV) {
+        Ok(value) if value.is_empty() || value == "0" => None,
+        Ok(value) => {
+            let blocks = value == "all";
+            Some(Box::new(move |event: &GcEvent| log_event(event, blocks)))
+        }
+        Err(_) => None,
+    }
+}
+
+/// Write a one-line description of the event to stderr
+fn log_event(event: &GcEvent, blocks: bool) {
+    match event {
+        GcEvent::CollectionStart { .. } => (),
+        GcEvent::CollectionEnd {
+            kind,
+            pause,
+            heap_bytes_before,
+            stats,
+        } => eprintln!(
+            "[gc {:?} {}K->{}K, {} blocks, {:.3}ms]",
+            kind,
+            heap_bytes_before / 1024,
+            stats.heap_bytes() / 1024,
+            stats.blocks.blocks_in_use,
+            pause.as_secs_f64() * 1000.0
+        ),
+        GcEvent::BlocksAcquired {
+            count,
+            blocks_in_use,
+        } if blocks => eprintln!("[gc acquired {} blocks, {} in use]", count, blocks_in_use),
+        GcEvent::BlocksReleased {
+            count,
+            blocks_in_use,
+        } if blocks => eprintln!("[gc released {} blocks, {} in use]", count, blocks_in_use),
+        GcEvent::OutOfMemory {
+            requested_bytes,
+            stats,
+        } => eprintln!(
+            "[gc out of memory allocating {} bytes, heap {}K]",
+            requested_bytes,
+            stats.heap_bytes() / 1024
+        ),
+        _ => (),
+    }
+}
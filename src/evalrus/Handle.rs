@@ -0,0 +1,98 @@
+use std::rc::{Rc, Weak};
+use crate::evalrus::MutatorView::MutatorView;
+use crate::evalrus::Ptrs::{CellPtr, ScopedPtr, TaggedCellPtr, TaggedScopedPtr};
+use crate::internals::Errors::{ErrorKind, RuntimeError};
+
+/// A pointer to an object that the host can hold between `Memory::mutate()` calls. The object is
+/// kept alive as a root until the last clone of the handle is dropped, and the handle follows it
+/// if the collector moves it.
+pub struct Handle<T: 'static> {
+    cell: Rc<CellPtr<T>>,
+    /// Identity of the heap the object lives in
+    heap: Weak<()>,
+}
+
+impl<T: 'static> Handle<T> {
+    /// Create a handle holding the given pointer. The caller registers the cell with the heap.
+    pub(crate) fn new(cell: Rc<CellPtr<T>>, heap: Weak<()>) -> Handle<T> {
+        Handle { cell, heap }
+    }
+
+    /// Return a scoped pointer to the object for use inside a mutator of the heap the handle
+    /// was created by
+    pub fn get<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+    ) -> Result<ScopedPtr<'guard, T>, RuntimeError> {
+        check_heap(&self.heap, mem)?;
+        Ok(self.cell.get(mem))
+    }
+}
+
+impl<T: 'static> Clone for Handle<T> {
+    fn clone(&self) -> Handle<T> {
+        Handle {
+            cell: self.cell.clone(),
+            heap: self.heap.clone(),
+        }
+    }
+}
+
+/// A handle to a value of any type, such as the result of evaluating an expression
+#[derive(Clone)]
+pub struct TaggedHandle {
+    cell: Rc<TaggedCellPtr>,
+    /// Identity of the heap the value lives in
+    heap: Weak<()>,
+}
+
+impl TaggedHandle {
+    /// Create a handle holding the given pointer. The caller registers the cell with the heap.
+    pub(crate) fn new(cell: Rc<TaggedCellPtr>, heap: Weak<()>) -> TaggedHandle {
+        TaggedHandle { cell, heap }
+    }
+
+    /// Return a scoped pointer to the value for use inside a mutator of the heap the handle was
+    /// created by
+    pub fn get<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        check_heap(&self.heap, mem)?;
+        Ok(self.cell.get(mem))
+    }
+}
+
+/// The cell of a handle is only rooted by, and only followed by collections of, the heap that
+/// created it
+fn check_heap(heap: &Weak<()>, mem: &MutatorView) -> Result<(), RuntimeError> {
+    if mem.heap.is_identified_by(heap) {
+        Ok(())
+    } else {
+        Err(RuntimeError::new(ErrorKind::ForeignHandle))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::evalrus::Heap::Heap;
+    use crate::evalrus::HeapConfig::HeapConfig;
+    use crate::evalrus::Pair::Pair;
+    use super::*;
+
+    #[test]
+    fn handle_is_rejected_by_another_heap() {
+        let owner = Heap::new(HeapConfig::default());
+        let other = Heap::new(HeapConfig::default());
+        let owner_mem = MutatorView { heap: &owner };
+        let other_mem = MutatorView { heap: &other };
+
+        let handle = owner_mem.tagged_handle(owner_mem.alloc_tagged(Pair::new()).unwrap());
+
+        assert!(handle.get(&owner_mem).is_ok());
+        match handle.get(&other_mem) {
+            Err(error) => assert_eq!(*error.error_kind(), ErrorKind::ForeignHandle),
+            Ok(_) => panic!("handle followed by a heap that did not create it"),
+        }
+    }
+}
@@ -1,35 +1,878 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::mem::size_of;
+use std::ptr::NonNull;
+use std::rc::{Rc, Weak};
+use std::time::{Duration, Instant};
+use crate::evalrus::AllocProfile::{AllocProfile, AllocSite, CallStack};
+use crate::evalrus::GcEvents::{CollectionKind, GcEvent, GcHook, GcHooks};
+use crate::evalrus::HeapConfig::HeapConfig;
+use crate::evalrus::HeapDump::HeapDump;
+use crate::evalrus::HeapStats::{CollectionStats, HeapStats, TypeStats};
 use crate::evalrus::ObjectHeader::ObjectHeader;
+use crate::evalrus::ParallelMark;
 use crate::evalrus::Ptrs::{FatPtr, ScopedPtr, ScopedRef, TaggedPtr};
 use crate::evalrus::SymbolMap::SymbolMap;
+use crate::evalrus::Trace::{Inspect, Trace, Tracer};
+use crate::evalrus::Traits::MutatorScope;
 use crate::evalrus::TypeList::TypeList;
-use crate::internals::Alloc::{AllocObject, AllocRaw, RawPtr};
+use crate::evalrus::Verify::{HeapError, Verifier};
+use crate::frontend::Array::ArraySize;
+use crate::internals::Alloc::{alloc_size_of, AllocError, AllocHeader, AllocObject, AllocRaw, RawPtr};
+use crate::internals::BlockList::{BlockTurnover, ReleasePolicy};
+use crate::internals::BlockSource::BlockSource;
 use crate::internals::Errors::RuntimeError;
 use crate::internals::StickyImmixHeap::StickyImmixHeap;
 
 pub type HeapStorage = StickyImmixHeap<ObjectHeader>;
+
 pub struct Heap {
     heap: HeapStorage,
     syms: SymbolMap,
+    /// Objects that survived a collection. Their marks stick until the next full collection
+    /// so that minor collections only trace objects allocated since the last cycle.
+    old: RefCell<Vec<NonNull<ObjectHeader>>>,
+    /// Pointer fields and containers written to since the last collection, keyed by address
+    remembered: RefCell<HashMap<usize, NonNull<dyn Trace>>>,
+    /// Addresses of objects that must never be moved by evacuation
+    pinned: RefCell<HashSet<usize>>,
+    /// Allocation counters per object type
+    type_stats: RefCell<HashMap<TypeList, TypeStats>>,
+    collection_stats: RefCell<CollectionStats>,
+    config: HeapConfig,
+    /// Objects that are always roots, such as a running Thread
+    roots: RefCell<Vec<NonNull<ObjectHeader>>>,
+    /// Pointer cells shared with host handles. A cell is dropped once no handle refers to it.
+    handles: RefCell<Vec<Rc<dyn Trace>>>,
+    /// Objects allocated since the last safepoint. They may only be referenced from Rust locals,
+    /// so collections triggered between safepoints treat them as roots.
+    allocated_since_safepoint: RefCell<Vec<NonNull<ObjectHeader>>>,
+    /// Bytes allocated since the last collection, to decide when to collect at a safepoint
+    allocated_since_collection: Cell<usize>,
+    /// Collections run at safepoints since the last full collection
+    collections_since_full: Cell<usize>,
+    /// Objects holding host resources, to be finalized when they die
+    finalizable: RefCell<Vec<NonNull<ObjectHeader>>>,
+    /// The tracer of the incremental collection in progress, if any
+    incremental: RefCell<Option<Tracer<'static>>>,
+    /// Allocations counted by site, when profiling
+    profile: RefCell<Option<AllocProfile>>,
+    /// The running code allocations are attributed to while profiling
+    call_stack: Cell<Option<NonNull<dyn CallStack>>>,
+    /// Callbacks told about collections, block turnover and allocation failures
+    hooks: Rc<GcHooks>,
+    /// Shared only as weak references, by handles, to tell this heap from any other
+    identity: Rc<()>,
+    /// Heap size when the incremental collection in progress started
+    incremental_start_bytes: Cell<usize>,
 }
 
 impl Heap {
+    pub fn new(config: HeapConfig) -> Heap {
+        let source = config
+            .block_source
+            .create()
+            .expect("could not reserve memory for the heap's block source");
+
+        Heap::with_block_source(config, source)
+    }
+
+    /// Create a heap whose blocks come from the given source, ignoring `config.block_source`
+    pub fn with_block_source(config: HeapConfig, source: Rc<dyn BlockSource>) -> Heap {
+        let heap = HeapStorage::new();
+        heap.set_block_source(source);
+        heap.set_limits(config.max_blocks, config.max_bytes);
+        heap.set_release_policy(config.release_policy);
+        heap.set_poison(config.debug);
+
+        let hooks = Rc::new(GcHooks::from_env());
+        let block_hooks = hooks.clone();
+        heap.set_turnover_listener(Box::new(move |turnover| {
+            block_hooks.emit(|| match turnover {
+                BlockTurnover::Acquired {
+                    count,
+                    blocks_in_use,
+                } => GcEvent::BlocksAcquired {
+                    count,
+                    blocks_in_use,
+                },
+                BlockTurnover::Released {
+                    count,
+                    blocks_in_use,
+                } => GcEvent::BlocksReleased {
+                    count,
+                    blocks_in_use,
+                },
+            })
+        }));
+
+        Heap {
+            heap,
+            syms: SymbolMap::new(),
+            old: RefCell::new(Vec::new()),
+            remembered: RefCell::new(HashMap::new()),
+            pinned: RefCell::new(HashSet::new()),
+            type_stats: RefCell::new(HashMap::new()),
+            collection_stats: RefCell::new(CollectionStats::default()),
+            config,
+            roots: RefCell::new(Vec::new()),
+            handles: RefCell::new(Vec::new()),
+            allocated_since_safepoint: RefCell::new(Vec::new()),
+            allocated_since_collection: Cell::new(0),
+            collections_since_full: Cell::new(0),
+            finalizable: RefCell::new(Vec::new()),
+            incremental: RefCell::new(None),
+            profile: RefCell::new(if config.profile_allocs {
+                Some(AllocProfile::new())
+            } else {
+                None
+            }),
+            call_stack: Cell::new(None),
+            hooks,
+            identity: Rc::new(()),
+            incremental_start_bytes: Cell::new(0),
+        }
+    }
+
+    /// Allocate an object. If the heap limit has been reached, run a full collection and try
+    /// again before failing with an OutOfMemory error.
     pub fn alloc<T>(&self, object: T) -> Result<RawPtr<T>, RuntimeError>
         where
             T: AllocObject<TypeList>,
     {
-        Ok(self.heap.alloc(object)?)
+        let ptr = match self.heap.try_alloc(object) {
+            Ok(ptr) => ptr,
+            Err((AllocError::OOM, object)) => {
+                self.collect_for_alloc();
+                self.heap
+                    .try_alloc(object)
+                    .map_err(|(error, _)| self.alloc_failed(error, size_of::<T>()))?
+            }
+            Err((error, _)) => return Err(error.into()),
+        };
+
+        self.record_alloc(T::TYPE_ID, size_of::<T>(), ptr.as_untyped());
+        Ok(ptr)
     }
     pub fn alloc_tagged<T>(&self, object: T) -> Result<TaggedPtr, RuntimeError>
         where
             FatPtr: From<RawPtr<T>>,
             T: AllocObject<TypeList>,
     {
-        Ok(TaggedPtr::from(FatPtr::from(self.heap.alloc(object)?)))
+        Ok(TaggedPtr::from(FatPtr::from(self.alloc(object)?)))
+    }
+
+    /// Allocate backing storage for an array of `size_bytes` bytes
+    pub fn alloc_array(&self, size_bytes: ArraySize) -> Result<RawPtr<u8>, RuntimeError> {
+        let ptr = match self.heap.alloc_array(size_bytes) {
+            Ok(ptr) => ptr,
+            Err(AllocError::OOM) => {
+                self.collect_for_alloc();
+                self.heap
+                    .alloc_array(size_bytes)
+                    .map_err(|error| self.alloc_failed(error, size_bytes as usize))?
+            }
+            Err(error) => return Err(error.into()),
+        };
+
+        self.record_alloc(TypeList::ArrayBackingBytes, size_bytes as usize, ptr.as_untyped());
+        Ok(ptr)
+    }
+
+    /// Count an allocation of an object of `object_size` bytes against its type and keep the
+    /// object alive until the next safepoint. Objects holding host resources are registered for
+    /// finalization.
+    fn record_alloc(&self, type_id: TypeList, object_size: usize, object: NonNull<()>) {
+        let alloc_size = alloc_size_of(size_of::<ObjectHeader>() + object_size);
+
+        let mut type_stats = self.type_stats.borrow_mut();
+        let stats = type_stats.entry(type_id).or_default();
+        stats.objects += 1;
+        stats.bytes += alloc_size;
+
+        self.allocated_since_collection
+            .set(self.allocated_since_collection.get() + alloc_size);
+        self.allocated_since_safepoint
+            .borrow_mut()
+            .push(HeapStorage::get_header(object));
+
+        if type_id == TypeList::Resource {
+            self.finalizable
+                .borrow_mut()
+                .push(HeapStorage::get_header(object));
+        }
+    }
+
+    /// Register a callback to be told about collections, block turnover and allocation failures
+    pub fn add_gc_hook(&self, hook: GcHook) {
+        self.hooks.add(hook);
+    }
+
+    /// Tell the hooks an allocation failed after collecting, passing the error on
+    fn alloc_failed(&self, error: AllocError, requested_bytes: usize) -> AllocError {
+        if error == AllocError::OOM {
+            self.hooks.emit(|| GcEvent::OutOfMemory {
+                requested_bytes,
+                stats: self.stats(),
+            });
+        }
+        error
+    }
+
+    /// Tell the hooks a collection is starting, returning the heap size for
+    /// `collection_finished()`. The size is only measured if there are hooks.
+    fn collection_started(&self, kind: CollectionKind) -> usize {
+        if self.hooks.is_empty() {
+            return 0;
+        }
+
+        let heap_bytes = self.stats().heap_bytes();
+        self.hooks.emit(|| GcEvent::CollectionStart {
+            kind,
+            heap_bytes,
+            allocated_bytes: self.allocated_since_collection.get(),
+        });
+        heap_bytes
+    }
+
+    /// Tell the hooks a collection has finished
+    fn collection_finished(&self, kind: CollectionKind, pause: Duration, heap_bytes_before: usize) {
+        self.hooks.emit(|| GcEvent::CollectionEnd {
+            kind,
+            pause,
+            heap_bytes_before,
+            stats: self.stats(),
+        });
+    }
+
+    /// Attribute an allocation of an object of `object_size` bytes to the site of the running
+    /// code, if profiling
+    pub fn profile_alloc(&self, guard: &dyn MutatorScope, type_id: TypeList, object_size: usize) {
+        if let Some(ref mut profile) = *self.profile.borrow_mut() {
+            let site = match self.call_stack.get() {
+                Some(call_stack) => unsafe { call_stack.as_ref().alloc_site(guard) },
+                None => AllocSite::host(),
+            };
+
+            profile.record(site, type_id, alloc_size_of(size_of::<ObjectHeader>() + object_size));
+        }
+    }
+
+    /// Set the running code that allocations are attributed to while profiling. The call stack
+    /// must be unregistered with `None` before it is dropped.
+    pub fn set_call_stack(&self, call_stack: Option<&(dyn CallStack + 'static)>) {
+        self.call_stack.set(call_stack.map(NonNull::from));
+    }
+
+    /// Start counting allocations by site, discarding any previous profile
+    pub fn start_profiling(&self) {
+        *self.profile.borrow_mut() = Some(AllocProfile::new());
+    }
+
+    /// Stop profiling allocations, returning the profile gathered
+    pub fn stop_profiling(&self) -> Option<AllocProfile> {
+        self.profile.borrow_mut().take()
+    }
+
+    /// Return a copy of the allocation profile gathered so far, if profiling
+    pub fn alloc_profile(&self) -> Option<AllocProfile> {
+        self.profile.borrow().clone()
+    }
+
+    /// Register a heap object that is marked and traced as a root by every collection until it
+    /// is removed. The object itself is marked, so it is never reclaimed while registered.
+    pub fn add_root(&self, object: NonNull<()>) {
+        self.roots.borrow_mut().push(HeapStorage::get_header(object));
+    }
+
+    /// Register a pointer cell held by a host handle, to be traced as a root until the last
+    /// handle to it is dropped
+    pub fn add_handle(&self, cell: Rc<dyn Trace>) {
+        self.handles.borrow_mut().push(cell);
+    }
+
+    /// Return a token identifying this heap for handles to hold
+    pub fn identity(&self) -> Weak<()> {
+        Rc::downgrade(&self.identity)
+    }
+
+    /// Return true if the token was given out by this heap. A held token keeps its allocation,
+    /// so no other heap can be given one at the same address.
+    pub fn is_identified_by(&self, identity: &Weak<()>) -> bool {
+        identity.as_ptr() == Rc::as_ptr(&self.identity)
+    }
+
+    /// Remove an object registered with `add_root()`
+    pub fn remove_root(&self, object: NonNull<()>) {
+        let header = HeapStorage::get_header(object);
+
+        self.roots
+            .borrow_mut()
+            .retain(|registered| *registered != header);
+    }
+
+    /// Called by the mutator where every live object is reachable from the registered roots.
+    /// Objects allocated since the last safepoint stop being treated as roots and, if enough
+    /// has been allocated since the last collection, a collection is run. In incremental mode a
+    /// full collection is started instead and advanced at each following safepoint.
+    ///
+    /// Full collections run here move objects out of sparsely occupied blocks, so no pointer
+    /// taken before the call may be used after it other than through the roots.
+    pub fn safepoint(&self) {
+        self.allocated_since_safepoint.borrow_mut().clear();
+
+        if self.incremental.borrow().is_some() {
+            self.mark_incrementally();
+            return;
+        }
+
+        if self.allocated_since_collection.get() >= self.config.collect_after_bytes {
+            if self.collections_since_full.get() + 1 >= self.config.full_collection_every {
+                if self.config.incremental {
+                    self.start_incremental();
+                } else {
+                    self.full_collection(None, true);
+                }
+            } else {
+                self.minor_collection(None);
+            }
+        }
+    }
+
+    /// Collect after an allocation failed. Nothing is moved: the mutator may hold pointers in
+    /// Rust locals that can't be updated.
+    fn collect_for_alloc(&self) {
+        self.full_collection(None, false);
+    }
+
+    /// Trace the registered roots, the cells of live handles and every object allocated since
+    /// the last safepoint
+    fn trace_roots(&self, tracer: &mut Tracer) {
+        for header in self.roots.borrow().iter() {
+            tracer.trace_header(*header);
+        }
+
+        let mut handles = self.handles.borrow_mut();
+        handles.retain(|cell| Rc::strong_count(cell) > 1);
+        for cell in handles.iter() {
+            cell.trace(tracer);
+        }
+
+        for header in self.allocated_since_safepoint.borrow().iter() {
+            tracer.trace_header(*header);
+        }
+    }
+
+    /// Return a snapshot of heap usage
+    pub fn stats(&self) -> HeapStats {
+        HeapStats {
+            blocks: self.heap.stats(),
+            types: self.type_stats.borrow().clone(),
+            collections: *self.collection_stats.borrow(),
+        }
     }
 
     pub fn lookup_sym(&self, name: &str) -> TaggedPtr {
         TaggedPtr::symbol(self.syms.lookup(name))
     }
 
+    /// Set how many entirely free blocks are kept for reuse after each collection; the rest
+    /// are returned to the OS
+    pub fn set_release_policy(&self, policy: ReleasePolicy) {
+        self.heap.set_release_policy(policy)
+    }
+
+    /// Record a written pointer field or container for the next minor collection. Writes to
+    /// memory outside of the heap - objects not yet moved into the heap - are ignored.
+    pub fn remember(&self, slot: &(dyn Trace + 'static)) {
+        let addr = slot as *const dyn Trace as *const u8;
+
+        if self.heap.contains(addr) {
+            self.remembered
+                .borrow_mut()
+                .insert(addr as usize, NonNull::from(slot));
+        }
+    }
+
+    /// Return true if the address lies within a block or large object of the heap
+    pub fn contains(&self, addr: *const u8) -> bool {
+        self.heap.contains(addr)
+    }
+
+    /// Check every block and every object reachable from the given roots and the registered
+    /// roots, returning the problems found. Objects can't be found other than by tracing so
+    /// unreachable garbage is not checked. While an incremental collection is marking, objects
+    /// allocated since it started may be marked, so the lines they sit on are not checked.
+    pub fn verify(&self, roots: Option<&dyn Trace>) -> Result<(), Vec<HeapError>> {
+        let marking = self.incremental.borrow().is_some();
+        let mut errors: Vec<HeapError> = self
+            .heap
+            .verify_blocks(!marking)
+            .into_iter()
+            .map(|(address, problem)| HeapError { address, problem })
+            .collect();
+
+        let mut verifier = Verifier::new(self);
+        self.inspect(roots, &mut verifier);
+        errors.extend(verifier.into_errors());
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Record the graph of objects reachable from the given roots and the registered roots
+    pub fn dump(&self, roots: Option<&dyn Trace>) -> HeapDump {
+        let mut dump = HeapDump::new();
+        self.inspect(roots, &mut dump);
+        dump
+    }
+
+    /// Walk every object reachable from the given roots and the registered roots with an
+    /// inspector, without marking or moving anything
+    fn inspect(&self, roots: Option<&dyn Trace>, inspector: &mut dyn Inspect) {
+        let mut tracer = Tracer::inspecting(inspector);
+
+        if let Some(roots) = roots {
+            roots.trace(&mut tracer);
+        }
+        self.trace_roots(&mut tracer);
+        tracer.trace_all();
+    }
+
+    /// In debug mode, verify the heap and panic if it is inconsistent
+    fn debug_verify(&self, roots: Option<&dyn Trace>, when: &str) {
+        if !self.config.debug {
+            return;
+        }
+
+        if let Err(errors) = self.verify(roots) {
+            let problems: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+            panic!("heap verification failed {}:\n{}", when, problems.join("\n"));
+        }
+    }
+
+    /// Prevent the object at the given address from being moved by evacuation, for objects
+    /// whose address is held somewhere the collector can't update
+    pub fn pin(&self, object: NonNull<()>) {
+        self.pinned.borrow_mut().insert(object.as_ptr() as usize);
+    }
+
+    /// Allow a pinned object to be moved again
+    pub fn unpin(&self, object: NonNull<()>) {
+        self.pinned.borrow_mut().remove(&(object.as_ptr() as usize));
+    }
+
+    /// After marking, forget the pins of objects that died so that an object allocated later at
+    /// the same address can be moved. Must run before the sweep reclaims the dead objects.
+    fn drop_dead_pins(&self) {
+        self.pinned.borrow_mut().retain(|object| {
+            let object = unsafe { NonNull::new_unchecked(*object as *mut ()) };
+            unsafe { HeapStorage::get_header(object).as_ref() }.is_marked()
+        });
+    }
+
+    /// Move the object following `header` out of a block selected for evacuation, leaving a
+    /// forwarding pointer behind. Returns the header of the copy, or None if the object stays
+    /// where it is: it is not in an evacuating block, is pinned, is too small to hold a
+    /// forwarding pointer or there is no space to copy it into.
+    pub fn evacuate(&self, header: NonNull<ObjectHeader>) -> Option<NonNull<ObjectHeader>> {
+        let object = HeapStorage::get_object(header);
+        let header_ref = unsafe { &mut *header.as_ptr() };
+
+        if !HeapStorage::is_evacuating(header)
+            || self.pinned.borrow().contains(&(object.as_ptr() as usize))
+            || (header_ref.size() as usize) < size_of::<usize>()
+        {
+            return None;
+        }
+
+        let new_header = self.heap.copy_object(header)?;
+        unsafe { header_ref.forward_to(HeapStorage::get_object(new_header)) };
+
+        Some(new_header)
+    }
+
+    /// Run a full collection cycle: clear all sticky marks, mark every object reachable from
+    /// the given roots and the registered roots and reclaim the lines of everything else.
+    /// Nothing is moved: the caller may hold pointers in Rust locals that can't be updated.
+    pub fn collect(&self, roots: &dyn Trace) {
+        self.full_collection(Some(roots), false)
+    }
+
+    /// Run a minor collection cycle, tracing from the given roots and the registered roots
+    pub fn collect_minor(&self, roots: &dyn Trace) {
+        self.minor_collection(Some(roots))
+    }
+
+    /// Objects in sparsely occupied blocks that are reached through pointer fields are moved
+    /// out and the fields updated if `evacuate` is set; objects referenced directly by the
+    /// roots are never moved.
+    fn full_collection(&self, roots: Option<&dyn Trace>, evacuate: bool) {
+        self.abort_incremental();
+        self.debug_verify(roots, "before a full collection");
+        let heap_bytes_before = self.collection_started(CollectionKind::Full);
+        let start = Instant::now();
+
+        for header in self.old.borrow_mut().drain(..) {
+            unsafe { (*header.as_ptr()).unmark() };
+        }
+
+        let mut tracer = if evacuate {
+            self.heap.select_evacuation_candidates();
+            Tracer::evacuating(self)
+        } else {
+            Tracer::new()
+        };
+
+        // mark phase
+        self.heap.start_collection();
+        if let Some(roots) = roots {
+            roots.trace(&mut tracer);
+        }
+        self.trace_roots(&mut tracer);
+        self.trace_all(&mut tracer, evacuate);
+        tracer.trace_ephemerons();
+        tracer.clear_weak();
+        self.finalize_dead();
+        self.drop_dead_pins();
+
+        // sweep phase
+        self.heap.finish_collection();
+
+        // survivors keep their marks until the next full collection
+        *self.old.borrow_mut() = tracer.into_marked();
+        self.remembered.borrow_mut().clear();
+
+        self.allocated_since_collection.set(0);
+        self.collections_since_full.set(0);
+        let pause = start.elapsed();
+        self.collection_stats.borrow_mut().record(true, pause);
+        self.collection_finished(CollectionKind::Full, pause, heap_bytes_before);
+
+        self.debug_verify(roots, "after a full collection");
+    }
+
+    /// Scan every grey object, on several threads if configured. Objects can only be moved by
+    /// a single mark thread.
+    fn trace_all(&self, tracer: &mut Tracer, evacuate: bool) {
+        if self.config.mark_threads > 1 && !evacuate {
+            ParallelMark::trace_all(tracer, self.config.mark_threads);
+        } else {
+            tracer.trace_all();
+        }
+    }
+
+    /// After marking, run the finalizer of every registered object that was not marked and
+    /// follow those that were moved. Must run before the sweep reclaims the dead objects.
+    fn finalize_dead(&self) {
+        self.finalizable.borrow_mut().retain_mut(|header| {
+            let header_ref = unsafe { &*header.as_ptr() };
+
+            if let Some(new_object) = header_ref.forwarded() {
+                *header = HeapStorage::get_header(new_object);
+                true
+            } else if header_ref.is_marked() {
+                true
+            } else {
+                unsafe { header_ref.finalize_object() };
+                false
+            }
+        });
+    }
+
+    /// Old objects are already marked so tracing stops at them; pointers from old objects to
+    /// new ones are found through the remembered set. Line marks are not reset so old objects'
+    /// lines are kept.
+    fn minor_collection(&self, roots: Option<&dyn Trace>) {
+        self.abort_incremental();
+        self.debug_verify(roots, "before a minor collection");
+        let heap_bytes_before = self.collection_started(CollectionKind::Minor);
+        let start = Instant::now();
+        let mut tracer = Tracer::new();
+
+        // mark phase
+        if let Some(roots) = roots {
+            roots.trace(&mut tracer);
+        }
+        self.trace_roots(&mut tracer);
+        for (_, slot) in self.remembered.borrow_mut().drain() {
+            unsafe { slot.as_ref().trace(&mut tracer) };
+        }
+        self.trace_all(&mut tracer, false);
+        tracer.trace_ephemerons();
+        tracer.clear_weak();
+        self.finalize_dead();
+        self.drop_dead_pins();
+
+        // sweep phase
+        self.heap.finish_collection();
+
+        // newly marked objects are promoted
+        self.old.borrow_mut().extend(tracer.into_marked());
+
+        self.allocated_since_collection.set(0);
+        self.collections_since_full
+            .set(self.collections_since_full.get() + 1);
+        let pause = start.elapsed();
+        self.collection_stats.borrow_mut().record(false, pause);
+        self.collection_finished(CollectionKind::Minor, pause, heap_bytes_before);
+
+        self.debug_verify(roots, "after a minor collection");
+    }
+
+    /// Start an incremental full collection: clear all sticky marks and grey the objects the
+    /// roots refer to. Line marks from the last collection are not cleared, so the mutator
+    /// keeps allocating only into lines that were free after it; marking steps only add marks,
+    /// and the final pause rebuilds them from the objects found live.
+    fn start_incremental(&self) {
+        self.debug_verify(None, "before an incremental collection");
+        self.incremental_start_bytes
+            .set(self.collection_started(CollectionKind::Incremental));
+        let start = Instant::now();
+
+        for header in self.old.borrow_mut().drain(..) {
+            unsafe { (*header.as_ptr()).unmark() };
+        }
+
+        let mut tracer = Tracer::new();
+        self.trace_roots(&mut tracer);
+
+        *self.incremental.borrow_mut() = Some(tracer);
+        self.collection_stats.borrow_mut().record_step(start.elapsed());
+    }
+
+    /// Scan up to the configured budget of grey objects, finishing the collection once none
+    /// remain
+    fn mark_incrementally(&self) {
+        let start = Instant::now();
+
+        let done = match *self.incremental.borrow_mut() {
+            Some(ref mut tracer) => tracer.trace_some(self.config.mark_budget),
+            None => return,
+        };
+
+        if done {
+            self.finish_incremental();
+        } else {
+            self.collection_stats.borrow_mut().record_step(start.elapsed());
+        }
+    }
+
+    /// The final pause of an incremental collection. The mutator may have stored pointers to
+    /// unmarked objects in objects already scanned, so the roots and every slot written since
+    /// the collection started are traced again before sweeping.
+    fn finish_incremental(&self) {
+        let start = Instant::now();
+        let mut tracer = match self.incremental.borrow_mut().take() {
+            Some(tracer) => tracer,
+            None => return,
+        };
+
+        self.trace_roots(&mut tracer);
+        for (_, slot) in self.remembered.borrow_mut().drain() {
+            unsafe { slot.as_ref().trace(&mut tracer) };
+        }
+        tracer.trace_all();
+        tracer.trace_ephemerons();
+        tracer.clear_weak();
+        self.finalize_dead();
+        self.drop_dead_pins();
+
+        // rebuild the line marks from the objects found live
+        let marked = tracer.into_marked();
+        self.heap.start_collection();
+        for header in &marked {
+            HeapStorage::mark_lines(*header);
+        }
+
+        // sweep phase
+        self.heap.finish_collection();
+        *self.old.borrow_mut() = marked;
+
+        self.allocated_since_collection.set(0);
+        self.collections_since_full.set(0);
+        let pause = start.elapsed();
+        self.collection_stats.borrow_mut().record(true, pause);
+        self.collection_finished(
+            CollectionKind::Incremental,
+            pause,
+            self.incremental_start_bytes.get(),
+        );
+
+        self.debug_verify(None, "after an incremental collection");
+    }
+
+    /// Abandon the incremental collection in progress, if any, clearing the marks it set so
+    /// that a stop-the-world collection can run
+    fn abort_incremental(&self) {
+        if let Some(tracer) = self.incremental.borrow_mut().take() {
+            for header in tracer.into_marked() {
+                unsafe { (*header.as_ptr()).unmark() };
+            }
+        }
+    }
 }
 
+/// Objects still holding host resources are finalized when the heap goes away
+impl Drop for Heap {
+    fn drop(&mut self) {
+        for header in self.finalizable.borrow_mut().drain(..) {
+            unsafe { header.as_ref().finalize_object() };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::evalrus::MutatorView::MutatorView;
+    use crate::evalrus::Pair::Pair;
+    use crate::evalrus::Value::Value;
+    use crate::internals::constants;
+    use super::*;
+
+    #[test]
+    fn store_into_scanned_object_survives_incremental_collection() {
+        let heap = Heap::new(HeapConfig {
+            collect_after_bytes: 1,
+            full_collection_every: 1,
+            incremental: true,
+            mark_budget: 1,
+            debug: true,
+            ..HeapConfig::default()
+        });
+        let mem = MutatorView { heap: &heap };
+
+        // a root with a chain behind it long enough to take several marking steps
+        let root = mem.alloc_tagged(Pair::new()).unwrap();
+        let handle = mem.tagged_handle(root);
+        let mut tail = root;
+        for _ in 0..10 {
+            let next = mem.alloc_tagged(Pair::new()).unwrap();
+            match *tail {
+                Value::Pair(pair) => pair.second.set(&mem, next),
+                _ => unreachable!(),
+            }
+            tail = next;
+        }
+
+        // the first safepoint greys the root, the second scans it
+        heap.safepoint();
+        heap.safepoint();
+        assert!(heap.incremental.borrow().is_some());
+
+        let stored = mem.alloc_tagged(Pair::new()).unwrap();
+        match *handle.get(&mem).unwrap() {
+            Value::Pair(pair) => pair.first.set(&mem, stored),
+            _ => unreachable!(),
+        }
+
+        while heap.incremental.borrow().is_some() {
+            heap.safepoint();
+        }
+
+        let object = stored.get_ptr().heap_object().unwrap();
+        assert!(unsafe { HeapStorage::get_header(object).as_ref() }.is_marked());
+        assert!(heap.verify(None).is_ok());
+    }
+
+    #[test]
+    fn verify_during_incremental_marking_allows_marked_new_objects() {
+        let heap = Heap::new(HeapConfig {
+            collect_after_bytes: 1,
+            full_collection_every: 1,
+            incremental: true,
+            mark_budget: 1,
+            ..HeapConfig::default()
+        });
+        let mem = MutatorView { heap: &heap };
+
+        let root = mem.alloc_tagged(Pair::new()).unwrap();
+        let _handle = mem.tagged_handle(root);
+        let mut tail = root;
+        for _ in 0..10 {
+            let next = mem.alloc_tagged(Pair::new()).unwrap();
+            match *tail {
+                Value::Pair(pair) => pair.second.set(&mem, next),
+                _ => unreachable!(),
+            }
+            tail = next;
+        }
+
+        heap.safepoint();
+        assert!(heap.incremental.borrow().is_some());
+
+        // a new object at the cursor, sharing its line with the rest of the current hole
+        let mut young = mem.alloc_tagged(Pair::new()).unwrap();
+        while HeapStorage::get_header(young.get_ptr().heap_object().unwrap()).as_ptr() as usize
+            % constants::LINE_SIZE
+            == 0
+        {
+            young = mem.alloc_tagged(Pair::new()).unwrap();
+        }
+        match *tail {
+            Value::Pair(pair) => pair.first.set(&mem, young),
+            _ => unreachable!(),
+        }
+
+        // mark the new object, but stop before the collection finishes
+        let header = HeapStorage::get_header(young.get_ptr().heap_object().unwrap());
+        while !unsafe { header.as_ref() }.is_marked() {
+            heap.safepoint();
+        }
+        assert!(heap.incremental.borrow().is_some());
+
+        assert!(heap.verify(None).is_ok());
+    }
+
+    #[test]
+    fn safepoint_full_collection_moves_objects_out_of_sparse_blocks() {
+        let heap = Heap::new(HeapConfig {
+            collect_after_bytes: 1,
+            full_collection_every: 1,
+            debug: true,
+            ..HeapConfig::default()
+        });
+        let mem = MutatorView { heap: &heap };
+
+        // a list spread thinly over several blocks, with garbage allocated between its pairs
+        let head = mem.alloc_tagged(Pair::new()).unwrap();
+        let list = mem.tagged_handle(head);
+        let mut kept = vec![head.get_ptr().heap_object()];
+        let mut tail = head;
+        for _ in 0..40 {
+            for _ in 0..100 {
+                mem.alloc_tagged(Pair::new()).unwrap();
+            }
+            let next = mem.alloc_tagged(Pair::new()).unwrap();
+            match *tail {
+                Value::Pair(pair) => pair.second.set(&mem, next),
+                _ => unreachable!(),
+            }
+            kept.push(next.get_ptr().heap_object());
+            tail = next;
+        }
+
+        // the first collection finds no recyclable blocks to evacuate and leaves the list's
+        // blocks sparsely occupied; the second moves the list out of them
+        heap.safepoint();
+        mem.alloc_tagged(Pair::new()).unwrap();
+        heap.safepoint();
+
+        let mut moved = 0;
+        let mut node = list.get(&mem).unwrap();
+        for old in &kept {
+            if node.get_ptr().heap_object() != *old {
+                moved += 1;
+            }
+            node = match *node {
+                Value::Pair(pair) => pair.second.get(&mem),
+                _ => unreachable!(),
+            };
+        }
+        assert!(moved > 0);
+        assert!(heap.verify(None).is_ok());
+    }
+}
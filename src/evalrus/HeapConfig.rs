@@ -0,0 +1,52 @@
+use crate::internals::BlockList::ReleasePolicy;
+use crate::internals::BlockSource::BlockSourceKind;
+use crate::internals::constants;
+
+/// Heap size limits and collection triggers, given to `Memory::with_config()`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct HeapConfig {
+    /// The most blocks the heap may hold, or None for no limit
+    pub max_blocks: Option<usize>,
+    /// The most bytes the heap may hold across blocks and large objects, or None for no limit
+    pub max_bytes: Option<usize>,
+    /// Collect at the next safepoint once this many bytes have been allocated since the last
+    /// collection
+    pub collect_after_bytes: usize,
+    /// Every this many safepoint collections, run a full collection instead of a minor one
+    pub full_collection_every: usize,
+    /// Number of threads to mark with. Collections that evacuate always mark on one thread, as
+    /// do collections that find only a few thousand objects to mark.
+    pub mark_threads: usize,
+    /// Run full collections started at safepoints incrementally, marking a little at each
+    /// safepoint rather than stopping the mutator for the whole cycle
+    pub incremental: bool,
+    /// The most objects an incremental marking step scans
+    pub mark_budget: usize,
+    /// How many entirely free blocks are kept for reuse after each collection
+    pub release_policy: ReleasePolicy,
+    /// Where the memory for heap blocks comes from
+    pub block_source: BlockSourceKind,
+    /// Count allocations by the function, source position and type they were made at
+    pub profile_allocs: bool,
+    /// Verify the heap before and after every collection, panicking on any problem found, and
+    /// poison reclaimed lines
+    pub debug: bool,
+}
+
+impl Default for HeapConfig {
+    fn default() -> HeapConfig {
+        HeapConfig {
+            max_blocks: None,
+            max_bytes: None,
+            collect_after_bytes: constants::DEFAULT_COLLECT_AFTER_BYTES,
+            full_collection_every: constants::DEFAULT_FULL_COLLECTION_EVERY,
+            mark_threads: constants::DEFAULT_MARK_THREADS,
+            incremental: false,
+            mark_budget: constants::DEFAULT_MARK_BUDGET,
+            release_policy: ReleasePolicy::RetainAtMost(constants::DEFAULT_FREE_BLOCKS_RETAINED),
+            block_source: BlockSourceKind::System,
+            profile_allocs: false,
+            debug: false,
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::ptr::NonNull;
+use crate::evalrus::Heap::HeapStorage;
+use crate::evalrus::ObjectHeader::ObjectHeader;
+use crate::evalrus::Trace::Inspect;
+use crate::evalrus::TypeList::TypeList;
+use crate::internals::Alloc::{AllocHeader, AllocRaw};
+
+/// A live object and the addresses of the objects it points to
+#[derive(Clone, Debug, PartialEq)]
+pub struct DumpObject {
+    pub address: usize,
+    pub type_id: TypeList,
+    /// Object size in bytes, not including the header
+    pub size: u32,
+    pub edges: Vec<usize>,
+}
+
+/// The object graph reachable from a set of roots
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HeapDump {
+    /// Addresses of the objects referenced directly by roots
+    pub roots: Vec<usize>,
+    pub objects: Vec<DumpObject>,
+    /// Index into `objects` by object address
+    index: HashMap<usize, usize>,
+}
+
+impl HeapDump {
+    pub fn new() -> HeapDump {
+        HeapDump::default()
+    }
+
+    /// Render the dump as a JSON document with `roots` and `objects` keys. Addresses are hex
+    /// strings.
+    pub fn to_json(&self) -> String {
+        let roots: Vec<String> = self.roots.iter().map(|addr| format!("\"{:#x}\"", addr)).collect();
+
+        let objects: Vec<String> = self
+            .objects
+            .iter()
+            .map(|object| {
+                let edges: Vec<String> =
+                    object.edges.iter().map(|addr| format!("\"{:#x}\"", addr)).collect();
+
+                format!(
+                    "{{\"address\":\"{:#x}\",\"type\":\"{:?}\",\"size\":{},\"edges\":[{}]}}",
+                    object.address,
+                    object.type_id,
+                    object.size,
+                    edges.join(",")
+                )
+            })
+            .collect();
+
+        format!(
+            "{{\"roots\":[{}],\"objects\":[\n{}\n]}}\n",
+            roots.join(","),
+            objects.join(",\n")
+        )
+    }
+
+    /// Render the dump as a Graphviz digraph. Roots are edges from a `roots` node.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph heap {\n    roots [shape=box];\n");
+
+        for object in &self.objects {
+            dot.push_str(&format!(
+                "    \"{:#x}\" [label=\"{:?}\\n{} bytes\"];\n",
+                object.address, object.type_id, object.size
+            ));
+        }
+
+        for root in &self.roots {
+            dot.push_str(&format!("    roots -> \"{:#x}\";\n", root));
+        }
+
+        for object in &self.objects {
+            for edge in &object.edges {
+                dot.push_str(&format!("    \"{:#x}\" -> \"{:#x}\";\n", object.address, edge));
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Write the dump to `<path>.json` and `<path>.dot`
+    pub fn write_files(&self, path: &Path) -> io::Result<()> {
+        fs::write(path.with_extension("json"), self.to_json())?;
+        fs::write(path.with_extension("dot"), self.to_dot())
+    }
+}
+
+impl Inspect for HeapDump {
+    /// Record the edge to the object, and the object itself the first time it is reached
+    fn visit(
+        &mut self,
+        source: Option<NonNull<ObjectHeader>>,
+        object: NonNull<()>,
+        _expected: Option<TypeList>,
+    ) -> Option<NonNull<ObjectHeader>> {
+        let address = object.as_ptr() as usize;
+
+        match source {
+            Some(source) => {
+                let source_address = HeapStorage::get_object(source).as_ptr() as usize;
+                if let Some(index) = self.index.get(&source_address) {
+                    self.objects[*index].edges.push(address);
+                }
+            }
+            None => self.roots.push(address),
+        }
+
+        if self.index.contains_key(&address) {
+            return None;
+        }
+
+        let header = HeapStorage::get_header(object);
+        let header_ref = unsafe { header.as_ref() };
+
+        self.index.insert(address, self.objects.len());
+        self.objects.push(DumpObject {
+            address,
+            type_id: header_ref.type_id(),
+            size: header_ref.size(),
+            edges: Vec::new(),
+        });
+
+        Some(header)
+    }
+}
@@ -0,0 +1,471 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fs;
+use std::mem::{size_of, take};
+use std::path::Path;
+use std::ptr::{copy_nonoverlapping, NonNull};
+use std::slice::from_raw_parts;
+use crate::evalrus::FatPtr::FatPtr;
+use crate::evalrus::Heap::HeapStorage;
+use crate::evalrus::MutatorView::MutatorView;
+use crate::evalrus::ObjectHeader::ObjectHeader;
+use crate::evalrus::Ptrs::{CellPtr, ScopedRef, TaggedCellPtr, TaggedPtr, TaggedScopedPtr};
+use crate::evalrus::Trace::{Inspect, Slot, Tracer};
+use crate::evalrus::Traits::MutatorScope;
+use crate::evalrus::TypeList::TypeList;
+use crate::frontend::Builtins::Builtin;
+use crate::frontend::Dicts::Dict;
+use crate::frontend::RawArray::RawArray;
+use crate::internals::Alloc::{AllocHeader, AllocRaw, RawPtr};
+use crate::internals::Errors::{ErrorKind, RuntimeError};
+
+/// Identifies a heap image file and its format version. Version 1 images saved raw type ids,
+/// which change as types are added, and are rejected.
+const IMAGE_MAGIC: &[u8; 8] = b"EVALIMG2";
+
+// What a saved pointer refers to
+const REF_NIL: u8 = 0;
+const REF_OBJECT: u8 = 1;
+const REF_SYMBOL: u8 = 2;
+const REF_NUMBER: u8 = 3;
+const REF_BOOLEAN: u8 = 4;
+const REF_CHAR: u8 = 5;
+
+// The kind of field a saved pointer is held in
+const SLOT_TAGGED: u8 = 0;
+const SLOT_RAW: u8 = 1;
+const SLOT_ARRAY: u8 = 2;
+
+fn image_error(reason: &str) -> RuntimeError {
+    RuntimeError::new(ErrorKind::IOError(format!("invalid heap image: {}", reason)))
+}
+
+/// Collects every object reachable from a root, and every pointer field of those objects, to
+/// be written to an image
+struct ImageWriter {
+    /// Headers of the objects found, in the order they were found
+    objects: Vec<NonNull<ObjectHeader>>,
+    /// Index into `objects` keyed by object address
+    index: HashMap<usize, u32>,
+    slots: Vec<Slot>,
+    /// Symbol names, indexed by the position they were first found in
+    symbols: Vec<String>,
+    symbol_index: HashMap<String, u32>,
+    /// Types of the objects found, saved by name, indexed by the position they were first
+    /// found in
+    types: Vec<TypeList>,
+    type_index: HashMap<TypeList, u16>,
+}
+
+impl ImageWriter {
+    fn new() -> ImageWriter {
+        ImageWriter {
+            objects: Vec::new(),
+            index: HashMap::new(),
+            slots: Vec::new(),
+            symbols: Vec::new(),
+            symbol_index: HashMap::new(),
+            types: Vec::new(),
+            type_index: HashMap::new(),
+        }
+    }
+
+    fn object_index(&self, object: NonNull<()>) -> Result<u32, RuntimeError> {
+        self.index
+            .get(&(object.as_ptr() as usize))
+            .copied()
+            .ok_or(image_error("pointer to an object that was not found"))
+    }
+
+    fn symbol(&mut self, name: &str) -> u32 {
+        if let Some(index) = self.symbol_index.get(name) {
+            return *index;
+        }
+
+        let index = self.symbols.len() as u32;
+        self.symbols.push(String::from(name));
+        self.symbol_index.insert(String::from(name), index);
+        index
+    }
+
+    fn type_index(&mut self, type_id: TypeList) -> u16 {
+        if let Some(index) = self.type_index.get(&type_id) {
+            return *index;
+        }
+
+        let index = self.types.len() as u16;
+        self.types.push(type_id);
+        self.type_index.insert(type_id, index);
+        index
+    }
+
+    /// Return the kind and index of the object or symbol a tagged pointer refers to, or of
+    /// the number it holds
+    fn reference(
+        &mut self,
+        guard: &dyn MutatorScope,
+        ptr: TaggedPtr,
+    ) -> Result<(u8, u64), RuntimeError> {
+        if let Some(object) = ptr.heap_object() {
+            return Ok((REF_OBJECT, self.object_index(object)? as u64));
+        }
+
+        match FatPtr::from(ptr) {
+            FatPtr::Nil => Ok((REF_NIL, 0)),
+            FatPtr::Number(value) => Ok((REF_NUMBER, value as u64)),
+            FatPtr::Boolean(value) => Ok((REF_BOOLEAN, value as u64)),
+            FatPtr::Char(value) => Ok((REF_CHAR, value as u64)),
+            FatPtr::Symbol(raw) => {
+                let name = raw.scoped_ref(guard).as_str(guard);
+                Ok((REF_SYMBOL, self.symbol(name) as u64))
+            }
+            _ => Err(image_error("unexpected pointer type")),
+        }
+    }
+
+    /// Return the index of the saved object containing the address, and the offset of the
+    /// address into it. `ranges` holds (start, end, index) sorted by start.
+    fn locate(ranges: &[(usize, usize, u32)], addr: usize) -> Option<(u32, u32)> {
+        let position = ranges.partition_point(|(start, _, _)| *start <= addr);
+        if position == 0 {
+            return None;
+        }
+
+        let (start, end, index) = ranges[position - 1];
+        if addr < end {
+            Some((index, (addr - start) as u32))
+        } else {
+            None
+        }
+    }
+
+    /// Serialize the objects found, their pointer fields and the root
+    fn write(
+        mut self,
+        guard: &dyn MutatorScope,
+        root: TaggedPtr,
+    ) -> Result<Vec<u8>, RuntimeError> {
+        let mut objects = Vec::new();
+        let mut ranges = Vec::new();
+
+        for (index, header) in self.objects.iter().enumerate() {
+            let header_ref = unsafe { header.as_ref() };
+            if header_ref.type_id() == TypeList::Resource {
+                return Err(image_error("Resource objects can't be saved"));
+            }
+
+            let object = HeapStorage::get_object(*header);
+            let size = header_ref.size() as usize;
+            let start = object.as_ptr() as usize;
+            ranges.push((start, start + size, index as u32));
+
+            let type_index = self.type_index(header_ref.type_id());
+            write_u16(&mut objects, type_index);
+            write_u32(&mut objects, size as u32);
+            objects.extend_from_slice(unsafe { from_raw_parts(start as *const u8, size) });
+        }
+        ranges.sort_unstable();
+
+        let mut slots = Vec::new();
+        let mut slot_count = 0u32;
+        for slot in take(&mut self.slots) {
+            let (addr, kind, (reference, value)) = match slot {
+                Slot::Tagged(cell) => {
+                    let ptr = unsafe { cell.as_ref() }.get_ptr();
+                    (cell.as_ptr() as usize, SLOT_TAGGED, self.reference(guard, ptr)?)
+                }
+                Slot::Raw(cell) => {
+                    let object = unsafe { cell.as_ref() }.get_raw().as_untyped();
+                    let index = self.object_index(object)? as u64;
+                    (cell.as_ptr() as usize, SLOT_RAW, (REF_OBJECT, index))
+                }
+                Slot::Array(data) => match unsafe { data.as_ref() }.get().as_ptr() {
+                    Some(ptr) => {
+                        let index = self.object_index(RawPtr::new(ptr).as_untyped())? as u64;
+                        (data.as_ptr() as usize, SLOT_ARRAY, (REF_OBJECT, index))
+                    }
+                    None => continue,
+                },
+            };
+
+            // immediates are already correct in the saved bytes
+            if matches!(reference, REF_NIL | REF_NUMBER | REF_BOOLEAN | REF_CHAR) {
+                continue;
+            }
+
+            // fields of roots are not saved
+            if let Some((holder, offset)) = ImageWriter::locate(&ranges, addr) {
+                write_u32(&mut slots, holder);
+                write_u32(&mut slots, offset);
+                slots.push(kind);
+                slots.push(reference);
+                write_u64(&mut slots, value);
+                slot_count += 1;
+            }
+        }
+
+        let (root_reference, root_value) = self.reference(guard, root)?;
+
+        let mut out = Vec::new();
+        out.extend_from_slice(IMAGE_MAGIC);
+
+        write_u32(&mut out, self.symbols.len() as u32);
+        for name in &self.symbols {
+            write_u32(&mut out, name.len() as u32);
+            out.extend_from_slice(name.as_bytes());
+        }
+
+        write_u16(&mut out, self.types.len() as u16);
+        for type_id in &self.types {
+            let name = format!("{:?}", type_id);
+            write_u32(&mut out, name.len() as u32);
+            out.extend_from_slice(name.as_bytes());
+        }
+
+        write_u32(&mut out, self.objects.len() as u32);
+        out.extend(objects);
+
+        write_u32(&mut out, slot_count);
+        out.extend(slots);
+
+        out.push(root_reference);
+        write_u64(&mut out, root_value);
+
+        Ok(out)
+    }
+}
+
+impl Inspect for ImageWriter {
+    fn visit(
+        &mut self,
+        _source: Option<NonNull<ObjectHeader>>,
+        object: NonNull<()>,
+        _expected: Option<TypeList>,
+    ) -> Option<NonNull<ObjectHeader>> {
+        let addr = object.as_ptr() as usize;
+        if self.index.contains_key(&addr) {
+            return None;
+        }
+
+        let header = HeapStorage::get_header(object);
+        self.index.insert(addr, self.objects.len() as u32);
+        self.objects.push(header);
+        Some(header)
+    }
+
+    fn slot(&mut self, slot: Slot) {
+        self.slots.push(slot);
+    }
+}
+
+/// Write every object reachable from `root` to a file. Symbols are saved by name. Objects
+/// holding host resources can't be saved.
+pub fn save_image(
+    mem: &MutatorView,
+    root: TaggedScopedPtr,
+    path: &Path,
+) -> Result<(), RuntimeError> {
+    let mut writer = ImageWriter::new();
+
+    let mut tracer = Tracer::inspecting(&mut writer);
+    tracer.trace_ptr(root.get_ptr());
+    tracer.trace_all();
+    drop(tracer);
+
+    let bytes = writer.write(mem, root.get_ptr())?;
+    fs::write(path, bytes).map_err(|e| RuntimeError::new(ErrorKind::IOError(e.to_string())))
+}
+
+/// Load an image written by `save_image()`, returning the root. Objects are copied into the heap
+/// as untyped bytes and only given their types once every pointer has been rebased, so a
+/// collection during loading never traces a half-loaded object. Like any new object, the
+/// loaded objects must be made reachable from a root before the next safepoint.
+pub fn load_image<'guard>(
+    mem: &'guard MutatorView,
+    path: &Path,
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let bytes =
+        fs::read(path).map_err(|e| RuntimeError::new(ErrorKind::IOError(e.to_string())))?;
+    let mut reader = Reader { bytes: &bytes, position: 0 };
+
+    if reader.take(IMAGE_MAGIC.len())? != IMAGE_MAGIC {
+        return Err(image_error("not a heap image"));
+    }
+
+    let mut symbols = Vec::new();
+    for _ in 0..reader.u32()? {
+        let length = reader.u32()? as usize;
+        let name = std::str::from_utf8(reader.take(length)?)
+            .map_err(|_| image_error("symbol name is not UTF-8"))?;
+        symbols.push(mem.heap.lookup_sym(name));
+    }
+
+    let mut types = Vec::new();
+    for _ in 0..reader.u16()? {
+        let length = reader.u32()? as usize;
+        let name = std::str::from_utf8(reader.take(length)?)
+            .map_err(|_| image_error("type name is not UTF-8"))?;
+        let type_id =
+            TypeList::from_name(name).ok_or(image_error(&format!("unknown type {}", name)))?;
+        if type_id == TypeList::Resource {
+            return Err(image_error("Resource objects can't be loaded"));
+        }
+        types.push(type_id);
+    }
+
+    let mut objects = Vec::new();
+    for _ in 0..reader.u32()? {
+        let type_id = *types
+            .get(reader.u16()? as usize)
+            .ok_or(image_error("type index out of range"))?;
+        let size = reader.u32()?;
+        if let Some(expected) = ObjectHeader::object_size(type_id) {
+            if size as usize != expected {
+                return Err(image_error(&format!("wrong size {} for a {:?}", size, type_id)));
+            }
+        }
+        let contents = reader.take(size as usize)?;
+
+        let object = mem.alloc_array(size)?;
+        unsafe {
+            copy_nonoverlapping(contents.as_ptr(), object.as_ptr() as *mut u8, size as usize)
+        };
+        objects.push((type_id, size, object.as_untyped()));
+    }
+
+    let object_at = |index: u64| -> Result<NonNull<()>, RuntimeError> {
+        objects
+            .get(index as usize)
+            .map(|(_, _, object)| *object)
+            .ok_or(image_error("object index out of range"))
+    };
+
+    let symbol_at = |index: u64| -> Result<TaggedPtr, RuntimeError> {
+        symbols
+            .get(index as usize)
+            .copied()
+            .ok_or(image_error("symbol index out of range"))
+    };
+
+    for _ in 0..reader.u32()? {
+        let (_, size, holder) = *objects
+            .get(reader.u32()? as usize)
+            .ok_or(image_error("object index out of range"))?;
+        let offset = reader.u32()? as usize;
+        let kind = reader.u8()?;
+        let reference = reader.u8()?;
+        let value = reader.u64()?;
+
+        let slot_size = match kind {
+            SLOT_TAGGED => size_of::<TaggedCellPtr>(),
+            SLOT_RAW => size_of::<CellPtr<()>>(),
+            SLOT_ARRAY => size_of::<Cell<RawArray<u8>>>(),
+            _ => return Err(image_error("bad field kind")),
+        };
+        if offset + slot_size > size as usize {
+            return Err(image_error("field lies outside of its object"));
+        }
+        let slot = unsafe { (holder.as_ptr() as *mut u8).add(offset) };
+
+        match (kind, reference) {
+            (SLOT_TAGGED, REF_OBJECT) => {
+                let cell = unsafe { &*(slot as *const TaggedCellPtr) };
+                cell.store(cell.get_ptr().relocated(object_at(value)?));
+            }
+            (SLOT_TAGGED, REF_SYMBOL) => {
+                let cell = unsafe { &*(slot as *const TaggedCellPtr) };
+                cell.store(symbol_at(value)?);
+            }
+            (SLOT_RAW, REF_OBJECT) => {
+                let cell = unsafe { &*(slot as *const CellPtr<()>) };
+                cell.relocate(RawPtr::new(object_at(value)?.as_ptr()));
+            }
+            (SLOT_ARRAY, REF_OBJECT) => {
+                let data = unsafe { &*(slot as *const Cell<RawArray<u8>>) };
+                data.set(data.get().relocated(object_at(value)?.cast::<u8>()));
+            }
+            _ => return Err(image_error("bad field reference")),
+        }
+    }
+
+    let root_reference = reader.u8()?;
+    let root_value = reader.u64()?;
+
+    // every pointer is valid now: give the objects their types
+    for (type_id, _, object) in &objects {
+        let header = unsafe { &mut *HeapStorage::get_header(*object).as_ptr() };
+        header.set_type_id(*type_id);
+    }
+
+    for (type_id, _, object) in &objects {
+        match type_id {
+            TypeList::Builtin => unsafe { object.cast::<Builtin>().as_ref() }.relink(mem)?,
+            TypeList::Dict => unsafe { object.cast::<Dict>().as_ref() }.rehash_identity_keys(),
+            _ => (),
+        }
+    }
+
+    let root = match root_reference {
+        REF_NIL => TaggedPtr::nil(),
+        REF_NUMBER => TaggedPtr::number(root_value as isize),
+        REF_BOOLEAN => TaggedPtr::boolean(root_value != 0),
+        REF_CHAR => TaggedPtr::character(
+            char::from_u32(root_value as u32).ok_or(image_error("bad char"))?,
+        ),
+        REF_SYMBOL => symbol_at(root_value)?,
+        REF_OBJECT => {
+            let header = HeapStorage::get_header(object_at(root_value)?);
+            TaggedPtr::from(unsafe { header.as_ref().get_object_fatptr() })
+        }
+        _ => return Err(image_error("bad root reference")),
+    };
+
+    Ok(TaggedScopedPtr::new(mem, root))
+}
+
+fn write_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_u64(out: &mut Vec<u8>, value: u64) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+/// Reads little-endian integers from an image, failing at the end of the data
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], RuntimeError> {
+        let end = self.position + count;
+        if end > self.bytes.len() {
+            return Err(image_error("unexpected end of file"));
+        }
+
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, RuntimeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, RuntimeError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, RuntimeError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, RuntimeError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use crate::evalrus::TypeList::TypeList;
+use crate::internals::BlockStats::BlockStats;
+use crate::internals::constants;
+
+/// Allocation counters for a single object type
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct TypeStats {
+    /// Count of objects allocated since the heap was created
+    pub objects: usize,
+    /// Bytes allocated since the heap was created, headers included
+    pub bytes: usize,
+}
+
+/// Collection cycle counters and pause times
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct CollectionStats {
+    pub full_collections: usize,
+    pub minor_collections: usize,
+    /// Total time spent in collections of either kind
+    pub total_time: Duration,
+    /// Duration of the most recent collection, or of the final pause of an incremental one
+    pub last_time: Duration,
+    /// The longest time the mutator was stopped for, by a collection or an incremental
+    /// marking step
+    pub max_pause: Duration,
+    /// Count of incremental marking steps run at safepoints
+    pub incremental_steps: usize,
+}
+
+impl CollectionStats {
+    /// Record a completed collection cycle
+    pub fn record(&mut self, full: bool, time: Duration) {
+        if full {
+            self.full_collections += 1;
+        } else {
+            self.minor_collections += 1;
+        }
+        self.total_time += time;
+        self.last_time = time;
+        self.max_pause = self.max_pause.max(time);
+    }
+
+    /// Record a step of incremental marking
+    pub fn record_step(&mut self, time: Duration) {
+        self.incremental_steps += 1;
+        self.total_time += time;
+        self.max_pause = self.max_pause.max(time);
+    }
+}
+
+/// A snapshot of heap usage
+#[derive(Clone, Debug, PartialEq)]
+pub struct HeapStats {
+    pub blocks: BlockStats,
+    /// Allocation counters for every type allocated at least once
+    pub types: HashMap<TypeList, TypeStats>,
+    pub collections: CollectionStats,
+}
+
+impl HeapStats {
+    /// Total bytes allocated across all types since the heap was created
+    pub fn bytes_allocated(&self) -> usize {
+        self.types.values().map(|stats| stats.bytes).sum()
+    }
+
+    /// Bytes currently held by the heap in blocks and large objects
+    pub fn heap_bytes(&self) -> usize {
+        self.blocks.blocks_in_use * constants::BLOCK_SIZE + self.blocks.large_object_bytes
+    }
+}
@@ -1,6 +1,13 @@
+use std::rc::Rc;
+use crate::evalrus::AllocProfile::AllocProfile;
+use crate::evalrus::GcEvents::GcEvent;
 use crate::evalrus::Heap::Heap;
+use crate::evalrus::HeapConfig::HeapConfig;
+use crate::evalrus::HeapDump::HeapDump;
+use crate::evalrus::HeapStats::HeapStats;
 use crate::evalrus::MutatorView;
 use crate::evalrus::Traits::Mutator;
+use crate::internals::BlockSource::BlockSource;
 use crate::internals::Errors::RuntimeError;
 
 pub struct Memory {
@@ -8,9 +15,60 @@ pub struct Memory {
 }
 
 impl Memory {
+    pub fn new() -> Memory {
+        Memory::with_config(HeapConfig::default())
+    }
+
+    /// Create a Memory whose heap is limited and collected according to `config`
+    pub fn with_config(config: HeapConfig) -> Memory {
+        Memory {
+            heap: Heap::new(config),
+        }
+    }
+
+    /// Create a Memory whose heap blocks come from the given source, such as a `FixedRegion`
+    /// over memory the host has set aside
+    pub fn with_block_source(config: HeapConfig, source: Rc<dyn BlockSource>) -> Memory {
+        Memory {
+            heap: Heap::with_block_source(config, source),
+        }
+    }
+
     pub fn mutate<M: Mutator>(&self, m: &M, input: M::Input) -> Result<M::Output, RuntimeError> {
         let mut guard = MutatorView::MutatorView::new(self);
         m.run(&mut guard, input)
     }
 
+    /// Record the graph of objects reachable from the registered roots, for the host to inspect
+    /// between mutations
+    pub fn heap_dump(&self) -> HeapDump {
+        self.heap.dump(None)
+    }
+
+    /// Register a callback to be told about collections, block acquire and release, and
+    /// allocation failures
+    pub fn on_gc_event<F: Fn(&GcEvent) + 'static>(&self, hook: F) {
+        self.heap.add_gc_hook(Box::new(hook))
+    }
+
+    /// Start counting allocations by site, discarding any previous profile
+    pub fn start_profiling(&self) {
+        self.heap.start_profiling()
+    }
+
+    /// Stop profiling allocations, returning the profile gathered
+    pub fn stop_profiling(&self) -> Option<AllocProfile> {
+        self.heap.stop_profiling()
+    }
+
+    /// Return a copy of the allocation profile gathered so far, if profiling
+    pub fn alloc_profile(&self) -> Option<AllocProfile> {
+        self.heap.alloc_profile()
+    }
+
+    /// Return a snapshot of heap usage, for the host to query between mutations
+    pub fn heap_stats(&self) -> HeapStats {
+        self.heap.stats()
+    }
+
 }
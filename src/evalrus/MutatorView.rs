@@ -1,7 +1,20 @@
+use std::mem::size_of;
+use std::path::Path;
+use std::ptr::NonNull;
+use std::rc::Rc;
+use crate::evalrus::AllocProfile::CallStack;
 use crate::evalrus::FatPtr::FatPtr;
-use crate::evalrus::Ptrs::{ScopedPtr, ScopedRef, TaggedScopedPtr};
+use crate::evalrus::Handle::{Handle, TaggedHandle};
+use crate::evalrus::Ptrs::{CellPtr, ScopedPtr, ScopedRef, TaggedCellPtr, TaggedPtr, TaggedScopedPtr};
 use crate::evalrus::Heap::Heap;
+use crate::evalrus::HeapDump::HeapDump;
+use crate::evalrus::HeapImage;
+use crate::evalrus::HeapStats::HeapStats;
+use crate::evalrus::Trace::Trace;
+use crate::evalrus::Traits::MutatorScope;
 use crate::evalrus::TypeList::TypeList;
+use crate::evalrus::Verify::HeapError;
+use crate::frontend::Array::ArraySize;
 use crate::internals::Alloc::{AllocObject, RawPtr};
 use crate::internals::Errors::RuntimeError;
 
@@ -14,10 +27,10 @@ impl<'memory> MutatorView<'memory> {
         where
             T: AllocObject<TypeList>,
     {
-        Ok(ScopedPtr::new(
-            self,
-            self.heap.alloc(object)?.scoped_ref(self),
-        ))
+        let ptr = self.heap.alloc(object)?;
+        self.heap.profile_alloc(self, T::TYPE_ID, size_of::<T>());
+
+        Ok(ScopedPtr::new(self, ptr.scoped_ref(self)))
     }
 }
 impl MutatorView {
@@ -26,7 +39,21 @@ impl MutatorView {
             FatPtr: From<RawPtr<T>>,
             T: AllocObject<TypeList>,
     {
-        Ok(TaggedScopedPtr::new(self, self.heap.alloc_tagged(object)?))
+        let ptr = self.heap.alloc_tagged(object)?;
+        self.heap.profile_alloc(self, T::TYPE_ID, size_of::<T>());
+
+        Ok(TaggedScopedPtr::new(self, ptr))
+    }
+}
+
+impl<'memory> MutatorView<'memory> {
+    /// Allocate backing storage for an array
+    pub fn alloc_array(&self, size_bytes: ArraySize) -> Result<RawPtr<u8>, RuntimeError> {
+        let ptr = self.heap.alloc_array(size_bytes)?;
+        self.heap
+            .profile_alloc(self, TypeList::ArrayBackingBytes, size_bytes as usize);
+
+        Ok(ptr)
     }
 }
 
@@ -35,4 +62,105 @@ impl<'memory> MutatorView<'memory> {
     pub fn lookup_sym(&self, name: &str) -> TaggedScopedPtr<'_> {
         TaggedScopedPtr::new(self, self.heap.lookup_sym(name))
     }
+
+    /// Return the `true` or `false` immediate
+    pub fn boolean(&self, value: bool) -> TaggedScopedPtr<'_> {
+        TaggedScopedPtr::new(self, TaggedPtr::boolean(value))
+    }
+
+    /// Return the immediate for a char
+    pub fn character(&self, value: char) -> TaggedScopedPtr<'_> {
+        TaggedScopedPtr::new(self, TaggedPtr::character(value))
+    }
+
+    /// Hand the mutator's roots to the collector and run a full collection cycle. Any object
+    /// not reachable from `roots` must not be referenced after this call. Nothing is moved.
+    pub fn collect(&self, roots: &dyn Trace) {
+        self.heap.collect(roots)
+    }
+
+    /// Run a minor collection, reclaiming only objects allocated since the last cycle
+    pub fn collect_minor(&self, roots: &dyn Trace) {
+        self.heap.collect_minor(roots)
+    }
+
+    /// Register a heap object, such as a running Thread, to be marked and traced as a root by
+    /// every collection until it is removed
+    pub fn add_root<T: AllocObject<TypeList>>(&self, object: &T) {
+        self.heap.add_root(NonNull::from(object).cast())
+    }
+
+    /// Remove an object registered with `add_root()`
+    pub fn remove_root<T: AllocObject<TypeList>>(&self, object: &T) {
+        self.heap.remove_root(NonNull::from(object).cast())
+    }
+
+    /// Attribute allocations to the given running code while profiling, or to the host if None
+    pub fn set_call_stack(&self, call_stack: Option<&(dyn CallStack + 'static)>) {
+        self.heap.set_call_stack(call_stack)
+    }
+
+    /// Root an object in a handle that the host can keep after this mutator returns
+    pub fn handle<T: 'static>(&self, object: ScopedPtr<'_, T>) -> Handle<T> {
+        let cell = Rc::new(CellPtr::new_with(object));
+        self.heap.add_handle(cell.clone());
+        Handle::new(cell, self.heap.identity())
+    }
+
+    /// Root a value in a handle that the host can keep after this mutator returns
+    pub fn tagged_handle(&self, value: TaggedScopedPtr<'_>) -> TaggedHandle {
+        let cell = Rc::new(TaggedCellPtr::new_with(value));
+        self.heap.add_handle(cell.clone());
+        TaggedHandle::new(cell, self.heap.identity())
+    }
+
+    /// Declare that every live object is reachable from the registered roots, giving the heap
+    /// the chance to collect. Objects may be moved, so references taken before the call must
+    /// be found again through the roots, such as handles, afterwards.
+    pub fn safepoint(&self) {
+        self.heap.safepoint()
+    }
+
+    /// Check every block and every object reachable from the given roots and the registered
+    /// roots for consistency
+    pub fn verify_heap(&self, roots: &dyn Trace) -> Result<(), Vec<HeapError>> {
+        self.heap.verify(Some(roots))
+    }
+
+    /// Record the graph of objects reachable from the given roots and the registered roots
+    pub fn heap_dump(&self, roots: Option<&dyn Trace>) -> HeapDump {
+        self.heap.dump(roots)
+    }
+
+    /// Write every object reachable from `root`, typically a Thread's globals Dict, to an image
+    /// file
+    pub fn save_image(&self, root: TaggedScopedPtr<'_>, path: &Path) -> Result<(), RuntimeError> {
+        HeapImage::save_image(self, root, path)
+    }
+
+    /// Load the objects saved in an image file into this heap, returning the saved root
+    pub fn load_image(&self, path: &Path) -> Result<TaggedScopedPtr<'_>, RuntimeError> {
+        HeapImage::load_image(self, path)
+    }
+
+    /// Return a snapshot of heap usage
+    pub fn heap_stats(&self) -> HeapStats {
+        self.heap.stats()
+    }
+
+    /// Prevent an object from being moved by the collector
+    pub fn pin<T>(&self, object: ScopedPtr<'_, T>) {
+        self.heap.pin(object.as_raw().as_untyped())
+    }
+
+    /// Allow a pinned object to be moved again
+    pub fn unpin<T>(&self, object: ScopedPtr<'_, T>) {
+        self.heap.unpin(object.as_raw().as_untyped())
+    }
+}
+
+impl<'memory> MutatorScope for MutatorView<'memory> {
+    fn write_barrier(&self, slot: &(dyn Trace + 'static)) {
+        self.heap.remember(slot)
+    }
 }
\ No newline at end of file
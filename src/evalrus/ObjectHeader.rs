@@ -1,13 +1,57 @@
+use std::mem::{size_of, transmute};
+use std::ptr::{read, NonNull};
+use std::sync::atomic::{AtomicU64, Ordering};
 use crate::evalrus::Heap::HeapStorage;
 use crate::evalrus::Ptrs::FatPtr;
+use crate::evalrus::Trace::{Trace, Tracer};
 use crate::evalrus::TypeList::TypeList::{self, *};
-use crate::internals::Alloc::{AllocRaw, Mark, RawPtr, SizeClass, Tagged};
+use crate::frontend::Array::ArraySize;
+use crate::frontend::Numbers::{Float, NumberObject};
+use crate::frontend::opcodes::{ArrayOpcode, ByteCode, InstructionStream};
+use crate::frontend::Resource::Resource;
+use crate::frontend::Text::Text;
+use crate::frontend::vm::{CallFrameList, Thread};
+use crate::internals::Alloc::{AllocHeader, AllocObject, AllocRaw, Mark, RawPtr, SizeClass, Tagged};
 
+/// Every object is preceded by a header packed into a single word:
+///
+/// | bits  | field      |
+/// |-------|------------|
+/// | 0-1   | mark       |
+/// | 2-3   | size class |
+/// | 4-15  | type id    |
+/// | 32-63 | size bytes |
 pub struct ObjectHeader {
-    mark: Mark,
-    size_class: SizeClass,
-    type_id: TypeList,
-    size_bytes: u32,
+    word: u64,
+}
+
+const MARK_SHIFT: u32 = 0;
+const MARK_MASK: u64 = 0x3;
+const SIZE_CLASS_SHIFT: u32 = 2;
+const SIZE_CLASS_MASK: u64 = 0x3;
+const TYPE_ID_SHIFT: u32 = 4;
+const TYPE_ID_MASK: u64 = 0xfff;
+const SIZE_SHIFT: u32 = 32;
+const SIZE_MASK: u64 = 0xffff_ffff;
+
+// The header must stay a single word for small objects such as Pairs to stay dense
+const _: () = assert!(size_of::<ObjectHeader>() == size_of::<u64>());
+
+/// Split a header word into mark, size class, type id and size
+fn unpack(word: u64) -> (u8, u8, u16, u32) {
+    (
+        ((word >> MARK_SHIFT) & MARK_MASK) as u8,
+        ((word >> SIZE_CLASS_SHIFT) & SIZE_CLASS_MASK) as u8,
+        ((word >> TYPE_ID_SHIFT) & TYPE_ID_MASK) as u16,
+        ((word >> SIZE_SHIFT) & SIZE_MASK) as u32,
+    )
+}
+
+fn pack(mark: Mark, size_class: SizeClass, type_id: TypeList, size: u32) -> u64 {
+    ((mark as u64) << MARK_SHIFT)
+        | ((size_class as u64) << SIZE_CLASS_SHIFT)
+        | ((type_id as u64 & TYPE_ID_MASK) << TYPE_ID_SHIFT)
+        | ((size as u64) << SIZE_SHIFT)
 }
 
 
@@ -17,11 +61,13 @@ impl ObjectHeader {
         let ptr_to_self = self.non_null_ptr();
         let object_addr = HeapStorage::get_object(ptr_to_self);
 
-        match self.type_id {
+        match self.type_id() {
             TypeList::ArrayU8 => FatPtr::ArrayU8(RawPtr::untag(object_addr.cast::<ArrayU8>())),
             TypeList::ArrayU16 => FatPtr::ArrayU16(RawPtr::untag(object_addr.cast::<ArrayU16>())),
             TypeList::ArrayU32 => FatPtr::ArrayU32(RawPtr::untag(object_addr.cast::<ArrayU32>())),
+            TypeList::Builtin => FatPtr::Builtin(RawPtr::untag(object_addr.cast::<Builtin>())),
             TypeList::Dict => FatPtr::Dict(RawPtr::untag(object_addr.cast::<Dict>())),
+            TypeList::Float => FatPtr::Float(RawPtr::untag(object_addr.cast::<Float>())),
             TypeList::Function => FatPtr::Function(RawPtr::untag(object_addr.cast::<Function>())),
             TypeList::List => FatPtr::List(RawPtr::untag(object_addr.cast::<List>())),
             TypeList::NumberObject => {
@@ -29,12 +75,251 @@ impl ObjectHeader {
             }
             TypeList::Pair => FatPtr::Pair(RawPtr::untag(object_addr.cast::<Pair>())),
             TypeList::Partial => FatPtr::Partial(RawPtr::untag(object_addr.cast::<Partial>())),
+            TypeList::Resource => {
+                FatPtr::Resource(RawPtr::untag(object_addr.cast::<Resource>()))
+            }
             TypeList::Symbol => FatPtr::Symbol(RawPtr::untag(object_addr.cast::<Symbol>())),
             TypeList::Text => FatPtr::Text(RawPtr::untag(object_addr.cast::<Text>())),
             TypeList::Upvalue => FatPtr::Upvalue(RawPtr::untag(object_addr.cast::<Upvalue>())),
+            TypeList::WeakRef => FatPtr::WeakRef(RawPtr::untag(object_addr.cast::<WeakRef>())),
 
             // Other types not represented by FatPtr are an error to id here
-            _ => panic!("Invalid ObjectHeader type tag {:?}!", self.type_id),
+            _ => panic!("Invalid ObjectHeader type tag {:?}!", self.type_id()),
+        }
+    }
+
+    /// Return the size in bytes of an object of the given type, or None for raw array storage
+    /// whose size varies
+    pub fn object_size(type_id: TypeList) -> Option<usize> {
+        match type_id {
+            TypeList::ArrayBackingBytes => None,
+            TypeList::ArrayOpcode => Some(size_of::<ArrayOpcode>()),
+            TypeList::ArrayU8 => Some(size_of::<ArrayU8>()),
+            TypeList::ArrayU16 => Some(size_of::<ArrayU16>()),
+            TypeList::ArrayU32 => Some(size_of::<ArrayU32>()),
+            TypeList::Builtin => Some(size_of::<Builtin>()),
+            TypeList::ByteCode => Some(size_of::<ByteCode>()),
+            TypeList::CallFrameList => Some(size_of::<CallFrameList>()),
+            TypeList::Dict => Some(size_of::<Dict>()),
+            TypeList::Float => Some(size_of::<Float>()),
+            TypeList::Function => Some(size_of::<Function>()),
+            TypeList::InstructionStream => Some(size_of::<InstructionStream>()),
+            TypeList::List => Some(size_of::<List>()),
+            TypeList::NumberObject => Some(size_of::<NumberObject>()),
+            TypeList::Pair => Some(size_of::<Pair>()),
+            TypeList::Partial => Some(size_of::<Partial>()),
+            TypeList::Resource => Some(size_of::<Resource>()),
+            TypeList::Symbol => Some(size_of::<Symbol>()),
+            TypeList::Text => Some(size_of::<Text>()),
+            TypeList::Thread => Some(size_of::<Thread>()),
+            TypeList::Upvalue => Some(size_of::<Upvalue>()),
+            TypeList::WeakRef => Some(size_of::<WeakRef>()),
+        }
+    }
+
+    /// Hand every pointer held by the object following this header to the tracer.
+    /// Types that can be represented by a FatPtr are traced through it, the VM-internal types
+    /// are cast directly.
+    pub unsafe fn trace_object(&self, tracer: &mut Tracer) {
+        let object_addr = HeapStorage::get_object(self.non_null_ptr());
+
+        match self.type_id() {
+            // Raw array storage is traced by the container that owns it
+            TypeList::ArrayBackingBytes => (),
+            TypeList::ArrayOpcode => object_addr.cast::<ArrayOpcode>().as_ref().trace(tracer),
+            TypeList::ByteCode => object_addr.cast::<ByteCode>().as_ref().trace(tracer),
+            TypeList::CallFrameList => object_addr.cast::<CallFrameList>().as_ref().trace(tracer),
+            TypeList::InstructionStream => {
+                object_addr.cast::<InstructionStream>().as_ref().trace(tracer)
+            }
+            TypeList::Thread => object_addr.cast::<Thread>().as_ref().trace(tracer),
+            _ => self.get_object_fatptr().trace(tracer),
+        }
+    }
+
+    /// Release the host-owned resources held by the dead object following this header. Only
+    /// types registered for finalization on allocation hold any.
+    pub unsafe fn finalize_object(&self) {
+        let object_addr = HeapStorage::get_object(self.non_null_ptr());
+
+        if self.type_id() == TypeList::Resource {
+            object_addr.cast::<Resource>().as_ref().close();
+        }
+    }
+
+    /// Atomically mark the object, returning false if it was already marked or forwarded, so
+    /// that mark threads racing to mark the same object scan it only once
+    pub fn try_mark(&self) -> bool {
+        let word = unsafe { &*(&self.word as *const u64 as *const AtomicU64) };
+        let mut current = word.load(Ordering::Acquire);
+
+        loop {
+            let mark = (current >> MARK_SHIFT) & MARK_MASK;
+            if mark == Mark::Marked as u64 || mark == Mark::Forwarded as u64 {
+                return false;
+            }
+
+            let marked =
+                (current & !(MARK_MASK << MARK_SHIFT)) | ((Mark::Marked as u64) << MARK_SHIFT);
+            match word.compare_exchange_weak(current, marked, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return true,
+                Err(actual) => current = actual,
+            }
+        }
+    }
+
+    /// Record that the object was evacuated to `new_object`. There is no room in the header
+    /// for an address so it is written over the first word of the old object, which is dead.
+    pub unsafe fn forward_to(&mut self, new_object: NonNull<()>) {
+        let old_object = HeapStorage::get_object(self.non_null_ptr());
+        *(old_object.as_ptr() as *mut usize) = new_object.as_ptr() as usize;
+        self.set_mark(Mark::Forwarded);
+    }
+
+    /// Return the new address of the object if it was evacuated during this collection
+    pub fn forwarded(&self) -> Option<NonNull<()>> {
+        if self.get_mark() == Mark::Forwarded {
+            let old_object = HeapStorage::get_object(self.non_null_ptr());
+            unsafe { NonNull::new(*(old_object.as_ptr() as *const usize) as *mut ()) }
+        } else {
+            None
+        }
+    }
+
+    /// Change the type of the object following this header, for objects whose contents are
+    /// written as plain bytes before they become valid, such as objects loaded from an image
+    pub fn set_type_id(&mut self, type_id: TypeList) {
+        self.word = (self.word & !(TYPE_ID_MASK << TYPE_ID_SHIFT))
+            | ((type_id as u64 & TYPE_ID_MASK) << TYPE_ID_SHIFT);
+    }
+
+    /// Return the mark bits. Only valid Mark values are ever written to a header.
+    fn get_mark(&self) -> Mark {
+        unsafe { transmute::<u8, Mark>(((self.word >> MARK_SHIFT) & MARK_MASK) as u8) }
+    }
+
+    fn set_mark(&mut self, mark: Mark) {
+        self.word = (self.word & !(MARK_MASK << MARK_SHIFT)) | ((mark as u64) << MARK_SHIFT);
+    }
+
+    /// Read the header fields as raw integers - mark, size class, type id and size - without
+    /// assuming they hold valid enum values, for verifying possibly corrupt headers
+    pub unsafe fn raw_fields(header: NonNull<ObjectHeader>) -> (u8, u8, u16, u32) {
+        unpack(read(header.as_ptr() as *const u64))
+    }
+
+    pub fn non_null_ptr(&self) -> NonNull<ObjectHeader> {
+        unsafe { NonNull::new_unchecked(self as *const ObjectHeader as *mut ObjectHeader) }
+    }
+}
+
+impl AllocHeader for ObjectHeader {
+    type TypeId = TypeList;
+
+    fn new<O: AllocObject<Self::TypeId>>(size: u32, size_class: SizeClass, mark: Mark) -> Self {
+        ObjectHeader {
+            word: pack(mark, size_class, O::TYPE_ID, size),
         }
     }
+
+    fn new_array(size: ArraySize, size_class: SizeClass, mark: Mark) -> Self {
+        ObjectHeader {
+            word: pack(mark, size_class, TypeList::ArrayBackingBytes, size as u32),
+        }
+    }
+
+    fn mark(&mut self) {
+        self.set_mark(Mark::Marked);
+    }
+
+    fn unmark(&mut self) {
+        self.set_mark(Mark::Unmarked);
+    }
+
+    fn is_marked(&self) -> bool {
+        self.get_mark() == Mark::Marked
+    }
+
+    /// Only valid SizeClass values are ever written to a header
+    fn size_class(&self) -> SizeClass {
+        let size_class = ((self.word >> SIZE_CLASS_SHIFT) & SIZE_CLASS_MASK) as u8;
+        unsafe { transmute::<u8, SizeClass>(size_class) }
+    }
+
+    fn size(&self) -> u32 {
+        ((self.word >> SIZE_SHIFT) & SIZE_MASK) as u32
+    }
+
+    /// Only valid TypeList values are ever written to a header
+    fn type_id(&self) -> TypeList {
+        let type_id = ((self.word >> TYPE_ID_SHIFT) & TYPE_ID_MASK) as u16;
+        unsafe { transmute::<u16, TypeList>(type_id) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+    use crate::evalrus::Heap::Heap;
+    use crate::evalrus::HeapConfig::HeapConfig;
+    use crate::evalrus::MutatorView::MutatorView;
+    use crate::evalrus::Pair::Pair;
+    use crate::evalrus::Ptrs::TaggedCellPtr;
+    use crate::evalrus::Value::Value;
+    use crate::internals::Alloc::alloc_size_of;
+    use crate::internals::constants;
+    use super::*;
+
+    /// The header layout before packing, with each field stored separately
+    #[allow(dead_code)]
+    struct FieldHeader {
+        mark: Mark,
+        size_class: SizeClass,
+        type_id: TypeList,
+        size_bytes: u32,
+    }
+
+    /// Build a long list of pairs and report the heap bytes each pair takes with the packed
+    /// header and with separate fields, and the time a full collection of the list takes.
+    /// Run with `cargo test --release pair_heavy -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn pair_heavy_benchmark() {
+        const PAIRS: usize = 100_000;
+        let heap = Heap::new(HeapConfig::default());
+        let mem = MutatorView { heap: &heap };
+
+        // the root is not a heap object, so it is written without the write barrier
+        let root = TaggedCellPtr::new_nil();
+        for _ in 0..PAIRS {
+            let pair = mem.alloc_tagged(Pair::new()).unwrap();
+            match *pair {
+                Value::Pair(p) => p.second.set(&mem, root.get(&mem)),
+                _ => unreachable!(),
+            }
+            root.store(pair.get_ptr());
+        }
+
+        let start = Instant::now();
+        mem.collect(&root);
+        let pause = start.elapsed();
+
+        let stats = mem.heap_stats();
+        let packed_bytes = stats.types[&TypeList::Pair].bytes / PAIRS;
+        let field_bytes = alloc_size_of(size_of::<FieldHeader>() + size_of::<Pair>());
+        let line_bytes = (stats.blocks.lines_marked * constants::LINE_SIZE) as f64 / PAIRS as f64;
+
+        println!(
+            "header: {} bytes packed, {} bytes as separate fields",
+            size_of::<ObjectHeader>(),
+            size_of::<FieldHeader>()
+        );
+        println!(
+            "per pair: {} bytes packed, {} bytes as separate fields, {:.1} bytes of marked lines",
+            packed_bytes, field_bytes, line_bytes
+        );
+        println!("full collection of {} pairs: {:?}", PAIRS, pause);
+
+        assert!(packed_bytes <= field_bytes);
+    }
 }
@@ -1,6 +1,8 @@
 use std::cell::Cell;
 use crate::evalrus::MutatorView::MutatorView;
 use crate::evalrus::Ptrs::{TaggedCellPtr, TaggedScopedPtr};
+use crate::evalrus::Trace::{Trace, Tracer};
+use crate::evalrus::Traits::MutatorScope;
 use crate::evalrus::TypeList::TypeList;
 use crate::frontend::Token::SourcePos;
 use crate::internals::Alloc::AllocObject;
@@ -31,16 +33,16 @@ impl Pair {
         value: TaggedScopedPtr<'guard>,
     ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
         let pair = Pair::new();
-        pair.first.set(value);
+        pair.first.set(mem, value);
 
         let pair = mem.alloc_tagged(pair)?;
-        self.second.set(pair);
+        self.second.set(mem, pair);
 
         Ok(pair)
     }
 
-    pub fn dot<'guard>(&self, value: TaggedScopedPtr<'guard>) {
-        self.second.set(value);
+    pub fn dot<'guard>(&self, guard: &'guard dyn MutatorScope, value: TaggedScopedPtr<'guard>) {
+        self.second.set(guard, value);
     }
 }
 
@@ -48,3 +50,10 @@ impl Pair {
 impl AllocObject<TypeList> for Pair {
     const TYPE_ID: TypeList = TypeList::Pair;
 }
+
+impl Trace for Pair {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.trace_tagged(&self.first);
+        tracer.trace_tagged(&self.second);
+    }
+}
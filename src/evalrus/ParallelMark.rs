@@ -0,0 +1,292 @@
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use crate::evalrus::ObjectHeader::ObjectHeader;
+use crate::evalrus::Trace::Tracer;
+
+/// Grey objects scanned by a mark thread between checks for work to share
+const SCAN_BATCH: usize = 32;
+/// A mark thread holding more grey objects than this shares half of them
+const SHARE_THRESHOLD: usize = 64;
+/// Grey objects scanned on the collecting thread before any mark thread is started, so that
+/// collections with little to mark, such as most minor collections, don't pay for starting
+/// threads
+const SERIAL_SCAN_LIMIT: usize = 4096;
+
+/// A batch of grey objects passed between mark threads. Objects are only read while marking
+/// without evacuation, and marks are set atomically, so scanning them from any thread is safe.
+struct Grey(Vec<NonNull<ObjectHeader>>);
+unsafe impl Send for Grey {}
+
+/// The objects marked and the weak objects found by a mark thread, handed back when it finishes
+struct MarkResults(Tracer<'static>);
+unsafe impl Send for MarkResults {}
+
+/// Work shared between mark threads: a queue per thread that idle threads steal from, and a
+/// count of the threads still scanning
+struct WorkPool {
+    queues: Vec<Mutex<Vec<Grey>>>,
+    active: AtomicUsize,
+}
+
+impl WorkPool {
+    /// Take a batch of grey objects, trying the thread's own queue before the others
+    fn steal(&self, thief: usize) -> Option<Vec<NonNull<ObjectHeader>>> {
+        for offset in 0..self.queues.len() {
+            let index = (thief + offset) % self.queues.len();
+            if let Some(Grey(batch)) = self.queues[index].lock().unwrap().pop() {
+                return Some(batch);
+            }
+        }
+        None
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queues.iter().all(|queue| queue.lock().unwrap().is_empty())
+    }
+}
+
+/// Scan every grey object held by the tracer and everything reachable from them using `threads`
+/// mark threads, merging the results back into the tracer. Marking starts on the calling thread
+/// and mark threads are only started if it finds more work than `SERIAL_SCAN_LIMIT` objects.
+/// Must not be used with an evacuating or inspecting tracer.
+pub fn trace_all(tracer: &mut Tracer, threads: usize) {
+    if tracer.trace_some(SERIAL_SCAN_LIMIT) {
+        return;
+    }
+
+    let pool = WorkPool {
+        queues: (0..threads).map(|_| Mutex::new(Vec::new())).collect(),
+        active: AtomicUsize::new(threads),
+    };
+
+    // deal the roots' objects out across the queues
+    let grey = tracer.take_stack();
+    for (index, batch) in grey.chunks(SHARE_THRESHOLD).enumerate() {
+        pool.queues[index % threads]
+            .lock()
+            .unwrap()
+            .push(Grey(batch.to_vec()));
+    }
+
+    let results: Vec<MarkResults> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads)
+            .map(|index| {
+                let pool = &pool;
+                scope.spawn(move || mark_thread(pool, index))
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("mark thread panicked"))
+            .collect()
+    });
+
+    for MarkResults(worker) in results {
+        tracer.merge(worker);
+    }
+}
+
+/// Scan grey objects until no thread has any left, sharing surplus work through the thread's
+/// own queue and stealing from the other threads' queues when out of work
+fn mark_thread(pool: &WorkPool, index: usize) -> MarkResults {
+    let mut tracer = Tracer::new();
+
+    loop {
+        while !tracer.trace_some(SCAN_BATCH) {
+            if tracer.stack_len() > SHARE_THRESHOLD {
+                pool.queues[index]
+                    .lock()
+                    .unwrap()
+                    .push(Grey(tracer.split_stack()));
+            }
+        }
+
+        if let Some(batch) = pool.steal(index) {
+            tracer.extend_stack(batch);
+            continue;
+        }
+
+        // Out of work: wait until another thread shares some or every thread is idle. A thread
+        // only holds grey objects while counted as active, so once none are active and the
+        // queues are empty, marking is complete.
+        pool.active.fetch_sub(1, Ordering::SeqCst);
+        loop {
+            if pool.active.load(Ordering::SeqCst) == 0 && pool.is_empty() {
+                return MarkResults(tracer);
+            }
+
+            if !pool.is_empty() {
+                pool.active.fetch_add(1, Ordering::SeqCst);
+                if let Some(batch) = pool.steal(index) {
+                    tracer.extend_stack(batch);
+                    break;
+                }
+                pool.active.fetch_sub(1, Ordering::SeqCst);
+            }
+
+            thread::yield_now();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use crate::evalrus::FatPtr::FatPtr;
+    use crate::evalrus::Heap::{Heap, HeapStorage};
+    use crate::evalrus::HeapConfig::HeapConfig;
+    use crate::evalrus::MutatorView::MutatorView;
+    use crate::evalrus::Pair::Pair;
+    use crate::evalrus::Ptrs::{TaggedPtr, TaggedScopedPtr};
+    use crate::evalrus::Value::Value;
+    use crate::frontend::Dicts::Dict;
+    use crate::frontend::Traits::{Container, HashIndexedAnyContainer};
+    use crate::frontend::WeakRef::WeakRef;
+    use crate::internals::Alloc::{AllocHeader, AllocRaw};
+    use crate::internals::constants;
+    use super::SERIAL_SCAN_LIMIT;
+
+    /// What a collection left behind: whether each object, in allocation order, is marked, the
+    /// line marks of each block holding the objects, whether the WeakRefs to a live and to a
+    /// dead object were cleared, and the number of entries left in a weak-keyed Dict
+    struct MarkState {
+        marked: Vec<bool>,
+        line_marks: Vec<Vec<u8>>,
+        weak_cleared: (bool, bool),
+        weak_dict_length: usize,
+    }
+
+    /// Allocate a Pair, recording it in `objects`
+    fn pair<'guard>(mem: &'guard MutatorView, objects: &mut Vec<TaggedPtr>) -> TaggedScopedPtr<'guard> {
+        let pair = mem.alloc_tagged(Pair::new()).unwrap();
+        objects.push(pair.get_ptr());
+        pair
+    }
+
+    fn tagged<'guard>(mem: &'guard MutatorView, object: FatPtr) -> TaggedScopedPtr<'guard> {
+        TaggedScopedPtr::new(mem, TaggedPtr::from(object))
+    }
+
+    /// Build the same graph on a fresh heap and run a minor collection with `mark_threads`.
+    /// Allocation is deterministic so objects land at the same offsets in the same order of
+    /// blocks whatever the number of mark threads.
+    fn mark_graph(mark_threads: usize) -> MarkState {
+        let heap = Heap::new(HeapConfig {
+            mark_threads,
+            ..HeapConfig::default()
+        });
+        let mem = MutatorView { heap: &heap };
+        let mut objects: Vec<TaggedPtr> = Vec::new();
+
+        // a list long enough to start mark threads, with an unreachable pair allocated between
+        // each pair of elements
+        let head = pair(&mem, &mut objects);
+        let mut tail = head;
+        for _ in 0..2 * SERIAL_SCAN_LIMIT {
+            pair(&mem, &mut objects);
+            let next = pair(&mem, &mut objects);
+            match *tail {
+                Value::Pair(tail_pair) => tail_pair.second.set(&mem, next),
+                _ => unreachable!(),
+            }
+            tail = next;
+        }
+
+        let dead = pair(&mem, &mut objects);
+        let weak_live = tagged(&mem, FatPtr::WeakRef(WeakRef::alloc(&mem, tail).unwrap().as_raw()));
+        let weak_dead = tagged(&mem, FatPtr::WeakRef(WeakRef::alloc(&mem, dead).unwrap().as_raw()));
+        objects.push(weak_live.get_ptr());
+        objects.push(weak_dead.get_ptr());
+
+        // ephemerons: the value under the live key survives, the value under the dead key not
+        let dict = Dict::alloc_weak(&mem).unwrap();
+        let live_value = pair(&mem, &mut objects);
+        let dead_value = pair(&mem, &mut objects);
+        dict.assoc(&mem, head, live_value).unwrap();
+        dict.assoc(&mem, dead, dead_value).unwrap();
+
+        // the root is not a heap object, so its fields are stored without the write barrier
+        let root = Pair::new();
+        root.first.store(head.get_ptr());
+        let weak_refs = pair(&mem, &mut objects);
+        match *weak_refs {
+            Value::Pair(weak_refs) => {
+                weak_refs.first.set(&mem, weak_live);
+                weak_refs.second.set(&mem, weak_dead);
+            }
+            _ => unreachable!(),
+        }
+        let holder = mem.alloc_tagged(Pair::new()).unwrap();
+        match *holder {
+            Value::Pair(holder) => {
+                holder.first.set(&mem, weak_refs);
+                holder.second.set(&mem, tagged(&mem, FatPtr::Dict(dict.as_raw())));
+            }
+            _ => unreachable!(),
+        }
+        root.second.store(holder.get_ptr());
+
+        heap.safepoint();
+        heap.collect_minor(&root);
+
+        let marked = objects
+            .iter()
+            .map(|object| {
+                let header = HeapStorage::get_header(object.heap_object().unwrap());
+                unsafe { header.as_ref() }.is_marked()
+            })
+            .collect();
+
+        let mut blocks = Vec::new();
+        let mut seen = HashSet::new();
+        for object in &objects {
+            let block = object.heap_object().unwrap().as_ptr() as usize & constants::BLOCK_PTR_MASK;
+            if seen.insert(block) {
+                blocks.push(block);
+            }
+        }
+        let line_marks = blocks
+            .iter()
+            .map(|block| {
+                let lines = (block + constants::LINE_MARK_START) as *const u8;
+                (0..constants::LINE_COUNT)
+                    .map(|index| unsafe { *lines.add(index) })
+                    .collect()
+            })
+            .collect();
+
+        let weak_cleared = match (*weak_live, *weak_dead) {
+            (Value::WeakRef(live), Value::WeakRef(dead)) => {
+                (live.get(&mem).is_nil(), dead.get(&mem).is_nil())
+            }
+            _ => unreachable!(),
+        };
+
+        MarkState {
+            marked,
+            line_marks,
+            weak_cleared,
+            weak_dict_length: dict.length() as usize,
+        }
+    }
+
+    #[test]
+    fn parallel_marking_matches_serial_marking() {
+        let serial = mark_graph(1);
+        let parallel = mark_graph(4);
+
+        assert_eq!(serial.marked, parallel.marked);
+        assert_eq!(serial.line_marks, parallel.line_marks);
+        assert_eq!(serial.weak_cleared, parallel.weak_cleared);
+        assert_eq!(serial.weak_dict_length, parallel.weak_dict_length);
+
+        // every other list allocation is garbage, and so are the dead key's pair and value
+        assert!(serial.marked[0]);
+        assert!(!serial.marked[1]);
+        assert_eq!(serial.weak_cleared, (false, true));
+        assert_eq!(serial.weak_dict_length, 1);
+    }
+}
@@ -4,6 +4,7 @@ use crate::evalrus::FatPtr::FatPtr;
 use crate::evalrus::Heap::HeapStorage;
 use crate::evalrus::Traits::MutatorScope;
 use crate::internals::Alloc::{AllocRaw, RawPtr, Tagged};
+use crate::evalrus::TypeList::TypeList;
 use crate::evalrus::TypeList::TypeList::*;
 use crate::evalrus::Value::Value;
 
@@ -15,6 +16,22 @@ pub const TAG_OBJECT: usize = 0x2;
 pub const TAG_NUMBER: usize = 0x3;
 const PTR_MASK: usize = !0x3;
 
+/// The booleans are immediates carrying the symbol tag, at addresses no Symbol can have, in the
+/// same way as nil is the zero word
+const TRUE_WORD: usize = 0x4;
+const FALSE_WORD: usize = 0x8;
+
+/// Chars are immediates carrying the symbol tag with the code point above the low byte. Symbols
+/// are word aligned, so a symbol-tagged word with bit 2 set is never a Symbol.
+const CHAR_MARK: usize = 0xc;
+const CHAR_MARK_MASK: usize = 0xff;
+const CHAR_SHIFT: usize = 8;
+
+/// Numbers are stored shifted left past the tag bits, so only this range fits in a TaggedPtr.
+/// Integer arithmetic moves results outside it into NumberObjects.
+pub const NUMBER_MAX: isize = isize::MAX >> 2;
+pub const NUMBER_MIN: isize = isize::MIN >> 2;
+
 
 #[derive(Clone, Debug)]
 pub struct CellPtr<T: Sized> {
@@ -40,6 +57,31 @@ impl<T: Sized> CellPtr<T> {
     pub fn get<'guard>(&self, guard: &'guard dyn MutatorScope) -> ScopedPtr<'guard, T> {
         ScopedPtr::new(guard, self.inner.get().scoped_ref(guard))
     }
+
+    /// Return the pointer held in the cell without a guard, for use by the collector
+    pub fn get_raw(&self) -> RawPtr<T> {
+        self.inner.get()
+    }
+
+    /// Point the cell at the new address of an object moved by the collector
+    pub fn relocate(&self, ptr: RawPtr<T>) {
+        self.inner.set(ptr);
+    }
+}
+
+impl<T: Sized + 'static> CellPtr<T> {
+    /// Point the cell at a new object, recording the write for the generational collector
+    pub fn set<'guard>(&self, guard: &'guard dyn MutatorScope, source: ScopedPtr<'guard, T>) {
+        self.inner.set(source.as_raw());
+        guard.write_barrier(self);
+    }
+}
+
+impl<'guard, T: Sized> ScopedPtr<'guard, T> {
+    /// Return the unscoped pointer to the object
+    pub fn as_raw(&self) -> RawPtr<T> {
+        RawPtr::new(self.value as *const T)
+    }
 }
 
 
@@ -52,6 +94,32 @@ impl TaggedCellPtr {
     pub fn get<'guard>(&self, guard: &'guard dyn MutatorScope) -> TaggedScopedPtr<'guard> {
         TaggedScopedPtr::new(guard, self.inner.get())
     }
+
+    /// Set the cell to a new value, recording the write for the generational collector
+    pub fn set<'guard>(&self, guard: &'guard dyn MutatorScope, source: TaggedScopedPtr<'guard>) {
+        self.set_to_ptr(guard, source.ptr);
+    }
+
+    /// Point the cell at the new address of an object moved by the collector
+    pub fn relocate(&self, ptr: TaggedPtr) {
+        self.inner.set(ptr);
+    }
+
+    /// Set the cell without recording the write. The caller must pass the object holding the
+    /// cell through the write barrier instead.
+    pub fn store(&self, ptr: TaggedPtr) {
+        self.inner.set(ptr);
+    }
+
+    /// Set the cell to a new unscoped pointer. Only writes of heap object pointers need to pass
+    /// through the write barrier.
+    pub fn set_to_ptr(&self, guard: &dyn MutatorScope, ptr: TaggedPtr) {
+        self.inner.set(ptr);
+
+        if ptr.heap_object().is_some() {
+            guard.write_barrier(self);
+        }
+    }
 }
 
 
@@ -77,7 +145,11 @@ impl From<FatPtr> for TaggedPtr {
             FatPtr::ArrayU8(raw) => TaggedPtr::object(raw),
             FatPtr::ArrayU16(raw) => TaggedPtr::object(raw),
             FatPtr::ArrayU32(raw) => TaggedPtr::object(raw),
+            FatPtr::Boolean(value) => TaggedPtr::boolean(value),
+            FatPtr::Builtin(raw) => TaggedPtr::object(raw),
+            FatPtr::Char(value) => TaggedPtr::character(value),
             FatPtr::Dict(raw) => TaggedPtr::object(raw),
+            FatPtr::Float(raw) => TaggedPtr::object(raw),
             FatPtr::Function(raw) => TaggedPtr::object(raw),
             FatPtr::List(raw) => TaggedPtr::object(raw),
             FatPtr::Nil => TaggedPtr::nil(),
@@ -85,9 +157,11 @@ impl From<FatPtr> for TaggedPtr {
             FatPtr::NumberObject(raw) => TaggedPtr::object(raw),
             FatPtr::Pair(raw) => TaggedPtr::pair(raw),
             FatPtr::Partial(raw) => TaggedPtr::object(raw),
+            FatPtr::Resource(raw) => TaggedPtr::object(raw),
             FatPtr::Text(raw) => TaggedPtr::object(raw),
             FatPtr::Symbol(raw) => TaggedPtr::symbol(raw),
             FatPtr::Upvalue(raw) => TaggedPtr::object(raw),
+            FatPtr::WeakRef(raw) => TaggedPtr::object(raw),
         }
     }
 }
@@ -95,10 +169,71 @@ impl From<FatPtr> for TaggedPtr {
 
 
 impl TaggedPtr {
+    /// Return the immediate for `true` or `false`
+    pub fn boolean(value: bool) -> TaggedPtr {
+        TaggedPtr {
+            tag: if value { TRUE_WORD } else { FALSE_WORD },
+        }
+    }
+
+    /// Return the immediate for a char
+    pub fn character(value: char) -> TaggedPtr {
+        TaggedPtr {
+            tag: ((value as usize) << CHAR_SHIFT) | CHAR_MARK,
+        }
+    }
+
+    /// Return false for nil and `false`, and true for every other value
+    pub fn is_truthy(&self) -> bool {
+        unsafe { self.tag != 0 && self.tag != FALSE_WORD }
+    }
+
+    /// Return the untagged address of the object this pointer refers to if it lives in the
+    /// collected heap. Nil, booleans, chars, numbers and symbols (which are allocated in the SymbolMap arena)
+    /// return None.
+    pub fn heap_object(&self) -> Option<NonNull<()>> {
+        unsafe {
+            if self.tag == 0 {
+                None
+            } else {
+                match get_tag(self.tag) {
+                    TAG_PAIR => Some(RawPtr::untag(self.pair).as_untyped()),
+                    TAG_OBJECT => Some(RawPtr::untag(self.object).as_untyped()),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    /// The object type the pointer's tag implies, if the tag identifies one
+    pub fn expected_type(&self) -> Option<TypeList> {
+        unsafe {
+            match get_tag(self.tag) {
+                TAG_PAIR => Some(TypeList::Pair),
+                _ => None,
+            }
+        }
+    }
+
+    /// Return a pointer with the same tag as this one but to a different heap object address
+    pub fn relocated(&self, object: NonNull<()>) -> TaggedPtr {
+        unsafe {
+            TaggedPtr {
+                tag: object.as_ptr() as usize | get_tag(self.tag),
+            }
+        }
+    }
+
     fn into_fat_ptr(&self) -> crate::evalrus::FatPtr::FatPtr {
         unsafe {
             if self.tag == 0 {
                 crate::evalrus::FatPtr::FatPtr::Nil
+            } else if self.tag == TRUE_WORD || self.tag == FALSE_WORD {
+                crate::evalrus::FatPtr::FatPtr::Boolean(self.tag == TRUE_WORD)
+            } else if self.tag & CHAR_MARK_MASK == CHAR_MARK {
+                // only ever constructed from a valid char
+                let value = char::from_u32_unchecked((self.tag >> CHAR_SHIFT) as u32);
+                crate::evalrus::FatPtr::FatPtr::Char(value)
             } else {
                 match get_tag(self.tag) {
                     TAG_NUMBER => crate::evalrus::FatPtr::FatPtr::Number(self.number >> 2),
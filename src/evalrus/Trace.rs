@@ -0,0 +1,413 @@
+use std::cell::Cell;
+use std::mem::replace;
+use std::ptr::NonNull;
+use crate::evalrus::Heap::{Heap, HeapStorage};
+use crate::evalrus::ObjectHeader::ObjectHeader;
+use crate::evalrus::Ptrs::{CellPtr, ScopedPtr, TaggedCellPtr, TaggedPtr};
+use crate::evalrus::TypeList::TypeList;
+use crate::frontend::RawArray::RawArray;
+use crate::internals::Alloc::{AllocHeader, AllocRaw, RawPtr};
+
+/// Implemented by every heap object type that holds pointers to other heap objects.
+/// `trace()` must hand every pointer field of the object to the tracer.
+pub trait Trace {
+    fn trace(&self, tracer: &mut Tracer);
+}
+
+/// A read-only walk of the object graph, run by a Tracer in place of marking
+pub trait Inspect {
+    /// Called for each pointer to `object`, held by the object following `source`, or by a root
+    /// if `source` is None. `expected` is the object type the pointer implies, if any. Returns
+    /// the object's header if its fields should be traced in turn.
+    fn visit(
+        &mut self,
+        source: Option<NonNull<ObjectHeader>>,
+        object: NonNull<()>,
+        expected: Option<TypeList>,
+    ) -> Option<NonNull<ObjectHeader>>;
+
+    /// Called with each pointer field of a scanned object, including fields holding nil, numbers
+    /// or symbols, before the object it points to is visited. Fields of roots are not reported.
+    fn slot(&mut self, _slot: Slot) {}
+}
+
+/// A pointer field found by an inspecting tracer. The pointee type is erased: a CellPtr or
+/// RawArray has the same layout whatever it points to.
+#[derive(Copy, Clone)]
+pub enum Slot {
+    Tagged(NonNull<TaggedCellPtr>),
+    Raw(NonNull<CellPtr<()>>),
+    /// The backing storage pointer of an array
+    Array(NonNull<Cell<RawArray<u8>>>),
+}
+
+/// Implemented by heap objects holding references that don't keep their targets alive on their
+/// own. Their `Trace` implementation hands them to `Tracer::trace_weak()`.
+pub trait Weak {
+    /// Trace the references that become strong once something else - an ephemeron key - is
+    /// known to be live. Called repeatedly until no more objects are reached.
+    fn trace_ephemerons(&self, tracer: &mut Tracer);
+
+    /// Trace every reference as if it were strong, for inspecting tracers
+    fn trace_all_refs(&self, tracer: &mut Tracer);
+
+    /// After marking, clear references to objects that died and update references to objects
+    /// that were moved
+    fn clear_dead(&self);
+}
+
+/// Return true if the object a pointer refers to was marked or moved in the current collection.
+/// Objects outside of the collected heap are always live.
+pub fn is_live(ptr: TaggedPtr) -> bool {
+    match ptr.heap_object() {
+        Some(object) => {
+            let header = unsafe { &*HeapStorage::get_header(object).as_ptr() };
+            header.is_marked() || header.forwarded().is_some()
+        }
+        None => true,
+    }
+}
+
+/// Return the pointer updated to the object's new address if it was moved in the current
+/// collection
+pub fn relocated(ptr: TaggedPtr) -> TaggedPtr {
+    if let Some(object) = ptr.heap_object() {
+        let header = unsafe { &*HeapStorage::get_header(object).as_ptr() };
+
+        if let Some(new_object) = header.forwarded() {
+            return ptr.relocated(new_object);
+        }
+    }
+
+    ptr
+}
+
+/// Mark phase state: a stack of grey objects - marked but not yet scanned - and a record of
+/// every object marked during the cycle.
+pub struct Tracer<'heap> {
+    /// Objects that have been marked but whose fields have not been traced yet
+    stack: Vec<NonNull<ObjectHeader>>,
+    /// Every object marked during this cycle
+    marked: Vec<NonNull<ObjectHeader>>,
+    /// Set when objects in blocks selected for evacuation should be moved as they are found
+    heap: Option<&'heap Heap>,
+    /// Set when objects should be inspected rather than marked
+    inspector: Option<&'heap mut dyn Inspect>,
+    /// The object whose fields are being traced, or None while tracing roots
+    scanning: Option<NonNull<ObjectHeader>>,
+    /// Objects holding weak references reached during this cycle
+    weak: Vec<NonNull<dyn Weak>>,
+}
+
+impl<'heap> Tracer<'heap> {
+    pub fn new() -> Tracer<'heap> {
+        Tracer {
+            stack: Vec::new(),
+            marked: Vec::new(),
+            heap: None,
+            inspector: None,
+            scanning: None,
+            weak: Vec::new(),
+        }
+    }
+
+    /// A tracer that moves objects reached through pointer fields out of the heap's
+    /// evacuation candidate blocks, fixing up the fields as it goes
+    pub fn evacuating(heap: &'heap Heap) -> Tracer<'heap> {
+        Tracer {
+            stack: Vec::new(),
+            marked: Vec::new(),
+            heap: Some(heap),
+            inspector: None,
+            scanning: None,
+            weak: Vec::new(),
+        }
+    }
+
+    /// A tracer that hands every pointer to the inspector without marking or moving anything
+    pub fn inspecting(inspector: &'heap mut dyn Inspect) -> Tracer<'heap> {
+        Tracer {
+            stack: Vec::new(),
+            marked: Vec::new(),
+            heap: None,
+            inspector: Some(inspector),
+            scanning: None,
+            weak: Vec::new(),
+        }
+    }
+
+    /// When inspecting, hand the pointer to the inspector and queue the object for scanning if
+    /// the inspector asks for it. Returns false if not inspecting.
+    fn inspect(&mut self, object: NonNull<()>, expected: Option<TypeList>) -> bool {
+        match self.inspector {
+            Some(ref mut inspector) => {
+                if let Some(header) = inspector.visit(self.scanning, object, expected) {
+                    self.stack.push(header);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// When inspecting, report a pointer field of the object being scanned
+    fn report_slot(&mut self, slot: Slot) {
+        if self.scanning.is_some() {
+            if let Some(ref mut inspector) = self.inspector {
+                inspector.slot(slot);
+            }
+        }
+    }
+
+    /// Mark the object behind the header and queue it for scanning, unless it was already
+    /// marked, possibly by another mark thread
+    fn mark_header(&mut self, header: NonNull<ObjectHeader>) {
+        let header_ref = unsafe { &*header.as_ptr() };
+
+        if header_ref.try_mark() {
+            HeapStorage::mark_lines(header);
+            self.marked.push(header);
+            self.stack.push(header);
+        }
+    }
+
+    /// Mark the object at the given address in place if it was not already marked. Objects
+    /// reached directly from roots are never moved because the root can't be updated.
+    fn mark_object(&mut self, object: NonNull<()>) {
+        if self.inspect(object, None) {
+            return;
+        }
+
+        self.mark_header(HeapStorage::get_header(object));
+    }
+
+    /// Mark the object at the given address, or move it if it sits in an evacuation candidate
+    /// block. Returns the address of the object after this collection.
+    fn visit(&mut self, object: NonNull<()>) -> NonNull<()> {
+        let header = HeapStorage::get_header(object);
+        let header_ref = unsafe { &*header.as_ptr() };
+
+        if let Some(forwarded) = header_ref.forwarded() {
+            return forwarded;
+        }
+
+        if !header_ref.is_marked() {
+            if let Some(new_header) = self.heap.and_then(|heap| heap.evacuate(header)) {
+                self.mark_header(new_header);
+                return HeapStorage::get_object(new_header);
+            }
+
+            self.mark_header(header);
+        }
+
+        object
+    }
+
+    /// Mark the object a tagged pointer root refers to. Nil, numbers and symbols are not
+    /// allocated in the collected heap and are ignored.
+    pub fn trace_ptr(&mut self, ptr: TaggedPtr) {
+        if let Some(object) = ptr.heap_object() {
+            if !self.inspect(object, ptr.expected_type()) {
+                self.mark_object(object);
+            }
+        }
+    }
+
+    /// Mark the object following a header, as a root
+    pub fn trace_header(&mut self, header: NonNull<ObjectHeader>) {
+        self.mark_object(HeapStorage::get_object(header));
+    }
+
+    /// Mark the object a raw pointer root refers to
+    pub fn trace_raw<T>(&mut self, ptr: RawPtr<T>) {
+        self.mark_object(ptr.as_untyped());
+    }
+
+    /// Trace the pointer held in a tagged pointer field, updating it if the object moved
+    pub fn trace_tagged(&mut self, cell: &TaggedCellPtr) {
+        self.report_slot(Slot::Tagged(NonNull::from(cell)));
+        let ptr = cell.get_ptr();
+
+        if let Some(object) = ptr.heap_object() {
+            if self.inspect(object, ptr.expected_type()) {
+                return;
+            }
+
+            let new_object = self.visit(object);
+
+            if new_object != object {
+                cell.relocate(ptr.relocated(new_object));
+            }
+        }
+    }
+
+    /// Trace the pointer held in a typed pointer field, updating it if the object moved
+    pub fn trace_cell<T>(&mut self, cell: &CellPtr<T>) {
+        self.report_slot(Slot::Raw(NonNull::from(cell).cast::<CellPtr<()>>()));
+        let object = cell.get_raw().as_untyped();
+        if self.inspect(object, None) {
+            return;
+        }
+
+        let new_object = self.visit(object);
+
+        if new_object != object {
+            cell.relocate(RawPtr::new(new_object.cast::<T>().as_ptr()));
+        }
+    }
+
+    /// Mark the backing storage of an array, updating the array if the storage moved. The
+    /// backing storage is untyped bytes so the items themselves must be traced by the owning
+    /// container.
+    pub fn trace_array<T>(&mut self, data: &Cell<RawArray<T>>) {
+        let array = data.get();
+
+        if let Some(ptr) = array.as_ptr() {
+            self.report_slot(Slot::Array(NonNull::from(data).cast::<Cell<RawArray<u8>>>()));
+            let object = RawPtr::new(ptr).as_untyped();
+            if self.inspect(object, Some(TypeList::ArrayBackingBytes)) {
+                return;
+            }
+
+            let new_object = self.visit(object);
+
+            if new_object != object {
+                data.set(array.relocated(new_object.cast::<T>()));
+            }
+        }
+    }
+
+    /// Record an object holding weak references, to be processed once strong marking is done.
+    /// Inspecting tracers follow weak references as if they were strong.
+    pub fn trace_weak(&mut self, object: &(dyn Weak + 'static)) {
+        if self.inspector.is_some() {
+            object.trace_all_refs(self);
+        } else {
+            self.weak.push(NonNull::from(object));
+        }
+    }
+
+    /// After `trace_all()`, trace ephemeron values whose keys were found live, repeating until
+    /// no more objects are reached
+    pub fn trace_ephemerons(&mut self) {
+        loop {
+            for index in 0..self.weak.len() {
+                let object = self.weak[index];
+                unsafe { object.as_ref().trace_ephemerons(self) };
+            }
+
+            if self.stack.is_empty() {
+                break;
+            }
+            self.trace_all();
+        }
+    }
+
+    /// Clear weak references to objects that were not marked. Must be called after
+    /// `trace_ephemerons()` and before sweeping.
+    pub fn clear_weak(&mut self) {
+        for object in self.weak.drain(..) {
+            unsafe { object.as_ref().clear_dead() };
+        }
+    }
+
+    /// Scan grey objects until every object reachable from the roots has been marked
+    pub fn trace_all(&mut self) {
+        while let Some(header) = self.stack.pop() {
+            self.scanning = Some(header);
+            unsafe { header.as_ref().trace_object(self) };
+        }
+        self.scanning = None;
+    }
+
+    /// Scan at most `budget` grey objects. Returns true once no grey objects remain.
+    pub fn trace_some(&mut self, budget: usize) -> bool {
+        for _ in 0..budget {
+            match self.stack.pop() {
+                Some(header) => {
+                    self.scanning = Some(header);
+                    unsafe { header.as_ref().trace_object(self) };
+                }
+                None => break,
+            }
+        }
+        self.scanning = None;
+
+        self.stack.is_empty()
+    }
+
+    /// Return the number of grey objects waiting to be scanned
+    pub fn stack_len(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// Take every grey object, to deal them out to mark threads
+    pub fn take_stack(&mut self) -> Vec<NonNull<ObjectHeader>> {
+        replace(&mut self.stack, Vec::new())
+    }
+
+    /// Take the oldest half of the grey objects, to share with idle mark threads
+    pub fn split_stack(&mut self) -> Vec<NonNull<ObjectHeader>> {
+        let half = self.stack.len() / 2;
+        self.stack.drain(..half).collect()
+    }
+
+    /// Queue grey objects taken from another tracer
+    pub fn extend_stack(&mut self, grey: Vec<NonNull<ObjectHeader>>) {
+        self.stack.extend(grey);
+    }
+
+    /// Take over the objects marked and the weak objects found by a mark thread's tracer
+    pub fn merge(&mut self, other: Tracer) {
+        self.marked.extend(other.marked);
+        self.weak.extend(other.weak);
+    }
+
+    /// Consume the tracer, returning every object marked during the cycle
+    pub fn into_marked(self) -> Vec<NonNull<ObjectHeader>> {
+        self.marked
+    }
+}
+
+/// A scoped pointer handed to the collector as a root marks the object itself as well as
+/// everything reachable from it.
+impl<'guard, T: Trace> Trace for ScopedPtr<'guard, T> {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.trace_raw(self.as_raw());
+    }
+}
+
+/// A group of roots can be handed over as a slice
+impl<'a> Trace for [&'a dyn Trace] {
+    fn trace(&self, tracer: &mut Tracer) {
+        for root in self {
+            root.trace(tracer);
+        }
+    }
+}
+
+/// A pointer field handed to the collector on its own, such as a remembered slot
+impl Trace for TaggedCellPtr {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.trace_tagged(self);
+    }
+}
+
+impl<T> Trace for CellPtr<T> {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.trace_cell(self);
+    }
+}
+
+/// Plain data array items hold no pointers
+impl Trace for u8 {
+    fn trace(&self, _tracer: &mut Tracer) {}
+}
+
+impl Trace for u16 {
+    fn trace(&self, _tracer: &mut Tracer) {}
+}
+
+impl Trace for u32 {
+    fn trace(&self, _tracer: &mut Tracer) {}
+}
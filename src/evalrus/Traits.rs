@@ -1,15 +1,20 @@
 use crate::evalrus::MutatorView::MutatorView;
+use crate::evalrus::Trace::Trace;
 use crate::internals::Errors::RuntimeError;
 
-pub trait MutatorScope {}
+pub trait MutatorScope {
+    /// Record that `slot` - a pointer field or a container - was written to, so that a minor
+    /// collection traces it even if the object holding it is old.
+    fn write_barrier(&self, _slot: &(dyn Trace + 'static)) {}
+}
+
+/// A Mutator is run inside a MutatorView scope. It hands its roots - for the VM, the Thread -
+/// to the collector through `MutatorView::collect()`.
 pub trait Mutator: Sized {
     type Input;
     type Output;
 
     fn run(&self, mem: &MutatorView, input: Self::Input) -> Result<Self::Output, RuntimeError>;
-
-    // TODO
-    // function to return iterator that iterates over roots
 }
 
 
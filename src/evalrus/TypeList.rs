@@ -1,26 +1,51 @@
+use std::mem::transmute;
 use crate::internals::Alloc::AllocTypeId;
 
+/// WeakRef must remain the last variant: `from_id()` depends on it. Ids are never persisted -
+/// heap images save types by name - so other variants may be added in any position.
 #[repr(u16)]
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum TypeList {
     ArrayBackingBytes,
     ArrayOpcode,
     ArrayU8,
     ArrayU16,
     ArrayU32,
+    Builtin,
     ByteCode,
     CallFrameList,
     Dict,
+    Float,
     Function,
     InstructionStream,
     List,
     NumberObject,
     Pair,
     Partial,
+    Resource,
     Symbol,
     Text,
     Thread,
     Upvalue,
+    WeakRef,
+}
+
+impl TypeList {
+    /// Convert a raw type id read from memory, returning None if it is not a valid id
+    pub fn from_id(id: u16) -> Option<TypeList> {
+        if id <= TypeList::WeakRef as u16 {
+            Some(unsafe { transmute::<u16, TypeList>(id) })
+        } else {
+            None
+        }
+    }
+
+    /// Look a type up by the name it is printed with, as saved in heap images
+    pub fn from_name(name: &str) -> Option<TypeList> {
+        (0..=TypeList::WeakRef as u16)
+            .filter_map(TypeList::from_id)
+            .find(|type_id| format!("{:?}", type_id) == name)
+    }
 }
 
 // Mark this as a Stickyimmix type-identifier type
@@ -8,7 +8,11 @@ pub enum Value<'guard> {
     ArrayU8(ScopedPtr<'guard, T::ArrayU8>),
     ArrayU16(ScopedPtr<'guard, T::ArrayU16>),
     ArrayU32(ScopedPtr<'guard, T::ArrayU32>),
+    Boolean(bool),
+    Builtin(ScopedPtr<'guard, T::Builtin>),
+    Char(char),
     Dict(ScopedPtr<'guard, T::Dict>),
+    Float(ScopedPtr<'guard, T::Float>),
     Function(ScopedPtr<'guard, T::Function>),
     List(ScopedPtr<'guard, T::List>),
     Nil,
@@ -16,8 +20,10 @@ pub enum Value<'guard> {
     NumberObject(ScopedPtr<'guard, T::NumberObject>),
     Pair(ScopedPtr<'guard, T::Pair>),
     Partial(ScopedPtr<'guard, T::Partial>),
+    Resource(ScopedPtr<'guard, T::Resource>),
     Symbol(ScopedPtr<'guard, T::Symbol>),
     Text(ScopedPtr<'guard, T::Text>),
     Upvalue(ScopedPtr<'guard, T::Upvalue>),
+    WeakRef(ScopedPtr<'guard, T::WeakRef>),
 }
 
@@ -0,0 +1,176 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::mem::size_of;
+use std::ptr::NonNull;
+use crate::evalrus::Heap::{Heap, HeapStorage};
+use crate::evalrus::ObjectHeader::ObjectHeader;
+use crate::evalrus::Trace::Inspect;
+use crate::evalrus::TypeList::TypeList;
+use crate::internals::Alloc::{alloc_size_of, AllocRaw, Mark, SizeClass};
+use crate::internals::constants;
+
+/// A problem found by heap verification
+#[derive(Clone, Debug, PartialEq)]
+pub struct HeapError {
+    /// Address of the object header or block the problem was found in
+    pub address: usize,
+    pub problem: String,
+}
+
+impl fmt::Display for HeapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#x}: {}", self.address, self.problem)
+    }
+}
+
+/// Verification state for an inspecting Tracer: instead of marking, each object reached is
+/// checked and, if valid, scanned once.
+pub struct Verifier<'heap> {
+    heap: &'heap Heap,
+    /// Header addresses already checked
+    visited: HashSet<usize>,
+    errors: Vec<HeapError>,
+}
+
+impl<'heap> Verifier<'heap> {
+    pub fn new(heap: &'heap Heap) -> Verifier<'heap> {
+        Verifier {
+            heap,
+            visited: HashSet::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn into_errors(self) -> Vec<HeapError> {
+        self.errors
+    }
+}
+
+impl<'heap> Inspect for Verifier<'heap> {
+    /// Check the object at the given address, which a pointer of `expected` type refers to.
+    /// Returns the header if the object is valid and has not been seen before.
+    fn visit(
+        &mut self,
+        _source: Option<NonNull<ObjectHeader>>,
+        object: NonNull<()>,
+        expected: Option<TypeList>,
+    ) -> Option<NonNull<ObjectHeader>> {
+        let header = HeapStorage::get_header(object);
+        let address = header.as_ptr() as usize;
+
+        if !self.visited.insert(address) {
+            return None;
+        }
+
+        match check_header(self.heap, header, expected) {
+            Ok(()) => Some(header),
+            Err(problem) => {
+                self.errors.push(HeapError { address, problem });
+                None
+            }
+        }
+    }
+}
+
+/// Check that a header lies in the heap and that its fields are consistent with each other
+/// and with the type of the pointer that led to it
+fn check_header(
+    heap: &Heap,
+    header: NonNull<ObjectHeader>,
+    expected: Option<TypeList>,
+) -> Result<(), String> {
+    if !heap.contains(header.as_ptr() as *const u8) {
+        return Err(String::from("pointer to an object outside of the heap"));
+    }
+
+    let (mark, size_class, type_id, size) = unsafe { ObjectHeader::raw_fields(header) };
+
+    let type_id = TypeList::from_id(type_id)
+        .ok_or_else(|| format!("invalid type id {}", type_id))?;
+
+    if let Some(expected) = expected {
+        if type_id != expected {
+            return Err(format!("expected a {:?} but found a {:?}", expected, type_id));
+        }
+    }
+
+    if mark > Mark::Marked as u8 {
+        return Err(format!("invalid mark {} on a {:?}", mark, type_id));
+    }
+
+    let alloc_size = alloc_size_of(size_of::<ObjectHeader>() + size as usize);
+    let expected_class = SizeClass::get_for_size(alloc_size)
+        .map_err(|_| format!("invalid size {} for a {:?}", size, type_id))?;
+
+    if size_class != expected_class as u8 {
+        return Err(format!(
+            "size class {} does not match size {} of a {:?}",
+            size_class, size, type_id
+        ));
+    }
+
+    if expected_class != SizeClass::Large {
+        let addr = header.as_ptr() as usize;
+        let offset = addr - (addr & constants::BLOCK_PTR_MASK);
+
+        if offset + alloc_size > constants::BLOCK_CAPACITY {
+            return Err(format!("{:?} overruns the end of its block", type_id));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr::write_bytes;
+    use crate::evalrus::HeapConfig::HeapConfig;
+    use crate::evalrus::Pair::Pair;
+    use crate::evalrus::Ptrs::TaggedCellPtr;
+    use crate::internals::BlockList::ReleasePolicy;
+    use super::*;
+
+    fn test_heap(debug: bool) -> Heap {
+        Heap::new(HeapConfig {
+            release_policy: ReleasePolicy::RetainAll,
+            debug,
+            ..HeapConfig::default()
+        })
+    }
+
+    #[test]
+    fn corrupted_header_is_reported() {
+        let heap = test_heap(false);
+        let root = TaggedCellPtr::new_nil();
+        root.store(heap.alloc_tagged(Pair::new()).unwrap());
+
+        let object = root.get_ptr().heap_object().unwrap();
+        let header = HeapStorage::get_header(object);
+        unsafe { write_bytes(header.as_ptr() as *mut u8, 0xff, size_of::<ObjectHeader>()) };
+
+        let errors = heap.verify(Some(&root)).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].address, header.as_ptr() as usize);
+        assert!(errors[0].problem.starts_with("invalid type id"));
+    }
+
+    #[test]
+    fn reclaimed_lines_are_poisoned() {
+        let heap = test_heap(true);
+        let dangling = TaggedCellPtr::new_nil();
+        dangling.store(heap.alloc_tagged(Pair::new()).unwrap());
+        let object = dangling.get_ptr().heap_object().unwrap();
+
+        // nothing but the cell, which is not given as a root, refers to the Pair
+        heap.safepoint();
+        heap.collect(&TaggedCellPtr::new_nil());
+
+        let header = HeapStorage::get_header(object).as_ptr() as *const u8;
+        for offset in 0..size_of::<ObjectHeader>() {
+            assert_eq!(unsafe { *header.add(offset) }, constants::POISON_BYTE);
+        }
+
+        let errors = heap.verify(Some(&dangling)).unwrap_err();
+        assert_eq!(errors[0].address, header as usize);
+    }
+}
@@ -1,10 +1,18 @@
 pub mod Ptrs;
+pub mod AllocProfile;
+pub mod GcEvents;
+pub mod Handle;
 pub mod Heap;
+pub mod HeapConfig;
+pub mod HeapDump;
+pub mod HeapImage;
+pub mod HeapStats;
 pub mod MutatorView;
 pub mod Memory;
 pub mod TypeList;
 pub mod Value;
 pub mod ObjectHeader;
+pub mod ParallelMark;
 pub mod FatPtr;
 pub mod Traits;
 pub mod Symbol;
@@ -12,4 +20,6 @@ pub mod Pair;
 pub mod SymbolMap;
 pub mod Arena;
 pub mod containers;
+pub mod Trace;
+pub mod Verify;
 
@@ -1,7 +1,8 @@
 use std::cell::Cell;
-use std::ptr::read;
+use std::ptr::{read, write};
 use crate::evalrus::MutatorView::MutatorView;
 use crate::evalrus::Ptrs::{ScopedPtr, TaggedCellPtr, TaggedScopedPtr};
+use crate::evalrus::Trace::{Trace, Tracer};
 use crate::evalrus::Traits::MutatorScope;
 use crate::evalrus::TypeList::TypeList;
 use crate::frontend::RawArray::RawArray;
@@ -88,6 +89,45 @@ impl<T: Sized + Clone> Array<T> {
                 Ok(&*dest as &T)
             }
         }
+
+    /// Mark the backing storage and hand each item in the array to `trace_item`
+    pub fn trace_with<F>(&self, tracer: &mut Tracer, trace_item: F)
+        where
+            F: Fn(&T, &mut Tracer),
+    {
+        tracer.trace_array(&self.data);
+
+        if let Some(ptr) = self.data.get().as_ptr() {
+            for index in 0..self.length.get() {
+                let item = unsafe { &*ptr.offset(index as isize) };
+                trace_item(item, tracer);
+            }
+        }
+    }
+}
+
+impl<T: Sized + Clone + Trace + 'static> Array<T> {
+    /// Move an item into the array at the given index. The array is recorded as written to so
+    /// that a minor collection traces its contents.
+    fn write<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        index: ArraySize,
+        item: T,
+    ) -> Result<&T, RuntimeError> {
+        unsafe {
+            let dest = self.get_offset(index)?;
+            write(dest, item);
+            guard.write_barrier(self);
+            Ok(&*dest as &T)
+        }
+    }
+}
+
+impl<T: Sized + Clone + Trace> Trace for Array<T> {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.trace_with(tracer, |item, tracer| item.trace(tracer));
+    }
 }
 
 impl<T: Sized + Clone> Container<T> for Array<T> {
@@ -125,7 +165,7 @@ impl<T: Sized + Clone> Container<T> for Array<T> {
 }
 
 
-impl<T: Sized + Clone> FillContainer<T> for Array<T> {
+impl<T: Sized + Clone + Trace + 'static> FillContainer<T> for Array<T> {
     fn fill<'guard>(
         &self,
         mem: &'guard MutatorView,
@@ -163,7 +203,7 @@ impl<T: Sized + Clone> FillContainer<T> for Array<T> {
 }
 
 
-impl<T: Sized + Clone> StackContainer<T> for Array<T> {
+impl<T: Sized + Clone + Trace + 'static> StackContainer<T> for Array<T> {
     /// Push can trigger an underlying array resize, hence it requires the ability to allocate
     // ANCHOR: DefStackContainerArrayPush
     fn push<'guard>(&self, mem: &'guard MutatorView, item: T) -> Result<(), RuntimeError> {
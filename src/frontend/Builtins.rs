@@ -0,0 +1,608 @@
+use std::cell::Cell;
+use std::cmp::Ordering;
+use std::path::Path;
+use crate::evalrus::MutatorView::MutatorView;
+use crate::evalrus::Ptrs::{ScopedPtr, TaggedCellPtr, TaggedScopedPtr};
+use crate::evalrus::Trace::{Trace, Tracer};
+use crate::evalrus::Traits::MutatorScope;
+use crate::evalrus::TypeList::TypeList;
+use crate::evalrus::Value::Value;
+use crate::frontend::Array::List;
+use crate::frontend::Dicts::Dict;
+use crate::frontend::Numbers;
+use crate::frontend::Text::{find, substring, Text};
+use crate::frontend::Traits::{Container, HashIndexedAnyContainer, StackAnyContainer};
+use crate::frontend::WeakRef::WeakRef;
+use crate::internals::Alloc::AllocObject;
+use crate::frontend::vm::err_eval;
+use crate::internals::Errors::{ErrorKind, RuntimeError};
+
+/// The signature of a function implemented in Rust. Arguments are passed as a slice of the
+/// caller's register window.
+pub type BuiltinFn = for<'guard> fn(
+    &'guard MutatorView,
+    &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError>;
+
+/// A function object implemented in Rust, callable from scripts like any Function
+#[derive(Clone)]
+pub struct Builtin {
+    /// The Symbol the builtin is bound to in the globals
+    name: TaggedCellPtr,
+    /// Number of arguments required to call the builtin
+    arity: u8,
+    /// Only changes when relinking a Builtin loaded from an image
+    function: Cell<BuiltinFn>,
+}
+
+impl Builtin {
+    /// Allocate a Builtin object on the heap
+    pub fn alloc<'guard>(
+        mem: &'guard MutatorView,
+        name: TaggedScopedPtr<'guard>,
+        arity: u8,
+        function: BuiltinFn,
+    ) -> Result<ScopedPtr<'guard, Builtin>, RuntimeError> {
+        mem.alloc(Builtin {
+            name: TaggedCellPtr::new_with(name),
+            arity,
+            function: Cell::new(function),
+        })
+    }
+
+    /// Return the Builtin's name as a string slice
+    pub fn name<'guard>(&self, guard: &'guard dyn MutatorScope) -> &'guard str {
+        let name = self.name.get(guard);
+        match *name {
+            Value::Symbol(s) => s.as_str(guard),
+            _ => "<builtin>",
+        }
+    }
+
+    /// Return the number of arguments the Builtin takes
+    pub fn arity(&self) -> u8 {
+        self.arity
+    }
+
+    /// Call the Builtin with the given arguments
+    pub fn call<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        args: &[TaggedCellPtr],
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        (self.function.get())(mem, args)
+    }
+
+    /// Point the Builtin at the implementation bound to its name in this process. Function
+    /// addresses saved in a heap image are not valid in another process.
+    pub fn relink(&self, guard: &dyn MutatorScope) -> Result<(), RuntimeError> {
+        let name = self.name(guard);
+
+        match BUILTINS.iter().find(|(builtin, _, _)| *builtin == name) {
+            Some((_, _, function)) => {
+                self.function.set(*function);
+                Ok(())
+            }
+            None => Err(err_eval(&format!("Unknown builtin {}", name))),
+        }
+    }
+}
+
+impl AllocObject<TypeList> for Builtin {
+    const TYPE_ID: TypeList = TypeList::Builtin;
+}
+
+impl Trace for Builtin {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.trace_tagged(&self.name);
+    }
+}
+
+/// Every builtin: the global name it is bound to, its arity and implementation
+const BUILTINS: &[(&str, u8, BuiltinFn)] = &[
+    ("heap-stats", 0, heap_stats),
+    ("heap-dump", 1, heap_dump),
+    ("make-weak-ref", 1, make_weak_ref),
+    ("weak-ref-get", 1, weak_ref_get),
+    ("make-weak-dict", 0, make_weak_dict),
+    ("dict-assoc", 3, dict_assoc),
+    ("dict-lookup", 2, dict_lookup),
+    ("close", 1, close),
+    ("=", 2, number_eq),
+    ("<", 2, number_lt),
+    ("<=", 2, number_le),
+    (">", 2, number_gt),
+    (">=", 2, number_ge),
+    ("text-length", 1, text_length),
+    ("text-concat", 2, text_concat),
+    ("substring", 3, text_substring),
+    ("text-find", 2, text_find),
+    ("text-split", 2, text_split),
+    ("text-join", 2, text_join),
+    ("text-upcase", 1, text_upcase),
+    ("text-downcase", 1, text_downcase),
+    ("text->symbol", 1, text_to_symbol),
+    ("symbol->text", 1, symbol_to_text),
+    ("text->number", 1, text_to_number),
+    ("number->text", 1, number_to_text),
+    ("text-ref", 2, text_ref),
+    ("char->integer", 1, char_to_integer),
+    ("integer->char", 1, integer_to_char),
+    ("char-alphabetic?", 1, char_is_alphabetic),
+    ("char-digit?", 1, char_is_digit),
+    ("char-whitespace?", 1, char_is_whitespace),
+];
+
+/// Bind every builtin to its name in the given globals Dict
+pub fn install<'guard>(
+    mem: &'guard MutatorView,
+    globals: ScopedPtr<'guard, Dict>,
+) -> Result<(), RuntimeError> {
+    for (name, arity, function) in BUILTINS {
+        let name = mem.lookup_sym(name);
+        let builtin = Builtin::alloc(mem, name, *arity, *function)?;
+        globals.assoc(mem, name, builtin.as_tagged(mem))?;
+    }
+
+    Ok(())
+}
+
+/// Return a count or size as a number, allocating a NumberObject if it is too large for a
+/// tagged integer
+fn number<'guard>(
+    mem: &'guard MutatorView,
+    value: usize,
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    Numbers::from_unsigned(mem, value as u128)
+}
+
+/// Return a copy of a Text argument. `Text::with_str` only lends the contents to a closure, so
+/// builtins copy them out before allocating the result.
+fn text_arg(
+    guard: &dyn MutatorScope,
+    arg: &TaggedCellPtr,
+    builtin: &str,
+) -> Result<String, RuntimeError> {
+    match *arg.get(guard) {
+        Value::Text(text) => Ok(text.to_string(guard)),
+        _ => Err(err_eval(&format!("{} expects a Text", builtin))),
+    }
+}
+
+/// Return a non-negative integer argument as a char index
+fn index_arg(
+    guard: &dyn MutatorScope,
+    arg: &TaggedCellPtr,
+    builtin: &str,
+) -> Result<usize, RuntimeError> {
+    match *arg.get(guard) {
+        Value::Number(n) if n >= 0 => Ok(n as usize),
+        _ => Err(err_eval(&format!("{} expects a non-negative integer index", builtin))),
+    }
+}
+
+/// Return a Char argument
+fn char_arg(
+    guard: &dyn MutatorScope,
+    arg: &TaggedCellPtr,
+    builtin: &str,
+) -> Result<char, RuntimeError> {
+    match *arg.get(guard) {
+        Value::Char(c) => Ok(c),
+        _ => Err(err_eval(&format!("{} expects a Char", builtin))),
+    }
+}
+
+/// Return a newly allocated Text
+fn text<'guard>(
+    mem: &'guard MutatorView,
+    value: &str,
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    Ok(Text::alloc(mem, value)?.as_tagged(mem))
+}
+
+/// Compare two numbers of any type, returning true if the ordering satisfies `test`.
+/// Comparisons involving NaN are always false.
+fn compare_numbers<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+    test: fn(Ordering) -> bool,
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let ordering = Numbers::compare(mem, args[0].get(mem), args[1].get(mem))?;
+    Ok(mem.boolean(ordering.map_or(false, test)))
+}
+
+/// (= a b) - return true if two numbers are numerically equal, so `(= 1 1.0)` is true
+fn number_eq<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    compare_numbers(mem, args, |ordering| ordering == Ordering::Equal)
+}
+
+/// (< a b) - return true if the number `a` is less than `b`
+fn number_lt<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    compare_numbers(mem, args, |ordering| ordering == Ordering::Less)
+}
+
+/// (<= a b) - return true if the number `a` is less than or equal to `b`
+fn number_le<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    compare_numbers(mem, args, |ordering| ordering != Ordering::Greater)
+}
+
+/// (> a b) - return true if the number `a` is greater than `b`
+fn number_gt<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    compare_numbers(mem, args, |ordering| ordering == Ordering::Greater)
+}
+
+/// (>= a b) - return true if the number `a` is greater than or equal to `b`
+fn number_ge<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    compare_numbers(mem, args, |ordering| ordering != Ordering::Less)
+}
+
+/// (heap-stats) - return a Dict of heap usage counters keyed by Symbol. The `types` entry is a
+/// Dict of bytes allocated keyed by type name.
+fn heap_stats<'guard>(
+    mem: &'guard MutatorView,
+    _args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let stats = mem.heap_stats();
+    let result = Dict::alloc(mem)?;
+
+    let counters = [
+        ("blocks-in-use", stats.blocks.blocks_in_use),
+        ("free-blocks", stats.blocks.free_blocks),
+        ("recyclable-blocks", stats.blocks.recyclable_blocks),
+        ("overflow-blocks", stats.blocks.overflow_blocks),
+        ("lines-marked", stats.blocks.lines_marked),
+        ("lines-free", stats.blocks.lines_free),
+        ("large-objects", stats.blocks.large_objects),
+        ("large-object-bytes", stats.blocks.large_object_bytes),
+        ("bytes-allocated", stats.bytes_allocated()),
+        ("full-collections", stats.collections.full_collections),
+        ("minor-collections", stats.collections.minor_collections),
+        ("incremental-steps", stats.collections.incremental_steps),
+    ];
+
+    for (name, value) in &counters {
+        result.assoc(mem, mem.lookup_sym(name), number(mem, *value)?)?;
+    }
+
+    let times = [
+        ("collection-micros", stats.collections.total_time.as_micros()),
+        ("max-pause-micros", stats.collections.max_pause.as_micros()),
+    ];
+
+    for (name, micros) in &times {
+        result.assoc(mem, mem.lookup_sym(name), Numbers::from_unsigned(mem, *micros)?)?;
+    }
+
+    let types = Dict::alloc(mem)?;
+    for (type_id, type_stats) in &stats.types {
+        let name = mem.lookup_sym(&format!("{:?}", type_id));
+        types.assoc(mem, name, number(mem, type_stats.bytes)?)?;
+    }
+    result.assoc(mem, mem.lookup_sym("types"), types.as_tagged(mem))?;
+
+    Ok(result.as_tagged(mem))
+}
+
+/// (heap-dump path) - write the graph of live objects to `path.json` and `path.dot`, where
+/// `path` is a Symbol. Returns the number of objects written.
+fn heap_dump<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let path = match *args[0].get(mem) {
+        Value::Symbol(s) => String::from(s.as_str(mem)),
+        _ => return Err(err_eval("heap-dump expects a Symbol path")),
+    };
+
+    let dump = mem.heap_dump(None);
+    dump.write_files(Path::new(&path))
+        .map_err(|e| RuntimeError::new(ErrorKind::IOError(e.to_string())))?;
+
+    number(mem, dump.objects.len())
+}
+
+/// (make-weak-ref object) - return a WeakRef to the object
+fn make_weak_ref<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    Ok(WeakRef::alloc(mem, args[0].get(mem))?.as_tagged(mem))
+}
+
+/// (weak-ref-get ref) - return the target of a WeakRef, or nil if it has been collected
+fn weak_ref_get<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    match *args[0].get(mem) {
+        Value::WeakRef(weak_ref) => Ok(weak_ref.get(mem)),
+        _ => Err(err_eval("weak-ref-get expects a WeakRef")),
+    }
+}
+
+/// (make-weak-dict) - return an empty Dict whose entries are removed once their keys are
+/// unreachable
+fn make_weak_dict<'guard>(
+    mem: &'guard MutatorView,
+    _args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    Ok(Dict::alloc_weak(mem)?.as_tagged(mem))
+}
+
+/// (dict-assoc dict key value) - associate the key with the value in the Dict, returning the
+/// value
+fn dict_assoc<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let value = args[2].get(mem);
+
+    match *args[0].get(mem) {
+        Value::Dict(dict) => {
+            dict.assoc(mem, args[1].get(mem), value)?;
+            Ok(value)
+        }
+        _ => Err(err_eval("dict-assoc expects a Dict")),
+    }
+}
+
+/// (dict-lookup dict key) - return the value associated with the key, or nil if there is none
+fn dict_lookup<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    match *args[0].get(mem) {
+        Value::Dict(dict) => match dict.lookup(mem, args[1].get(mem)) {
+            Ok(value) => Ok(value),
+            Err(e) if *e.error_kind() == ErrorKind::KeyError => Ok(mem.nil()),
+            Err(e) => Err(e),
+        },
+        _ => Err(err_eval("dict-lookup expects a Dict")),
+    }
+}
+
+/// (close resource) - release a Resource's host-owned value now rather than when it is
+/// collected. Closing a Resource twice has no effect.
+fn close<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    match *args[0].get(mem) {
+        Value::Resource(resource) => {
+            resource.close();
+            Ok(mem.nil())
+        }
+        _ => Err(err_eval("close expects a Resource")),
+    }
+}
+
+/// (text-length text) - return the number of chars in a Text
+fn text_length<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    match *args[0].get(mem) {
+        Value::Text(text) => number(mem, text.char_length(mem)),
+        _ => Err(err_eval("text-length expects a Text")),
+    }
+}
+
+/// (text-concat a b) - return a new Text of `a` followed by `b`
+fn text_concat<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let mut result = text_arg(mem, &args[0], "text-concat")?;
+    result.push_str(&text_arg(mem, &args[1], "text-concat")?);
+    text(mem, &result)
+}
+
+/// (substring text start end) - return the chars from index `start` up to but excluding `end`
+fn text_substring<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let value = text_arg(mem, &args[0], "substring")?;
+    let start = index_arg(mem, &args[1], "substring")?;
+    let end = index_arg(mem, &args[2], "substring")?;
+    text(mem, substring(&value, start, end)?)
+}
+
+/// (text-find text needle) - return the char index of the first occurrence of `needle`, or nil
+/// if there is none
+fn text_find<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let value = text_arg(mem, &args[0], "text-find")?;
+    let needle = text_arg(mem, &args[1], "text-find")?;
+
+    match find(&value, &needle) {
+        Some(index) => number(mem, index),
+        None => Ok(mem.nil()),
+    }
+}
+
+/// (text-split text separator) - return a List of the pieces of a Text between occurrences of a
+/// non-empty separator
+fn text_split<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let value = text_arg(mem, &args[0], "text-split")?;
+    let separator = text_arg(mem, &args[1], "text-split")?;
+    if separator.is_empty() {
+        return Err(err_eval("text-split expects a non-empty separator"));
+    }
+
+    let result = List::alloc(mem)?;
+    for piece in value.split(separator.as_str()) {
+        StackAnyContainer::push(&*result, mem, text(mem, piece)?)?;
+    }
+
+    Ok(result.as_tagged(mem))
+}
+
+/// (text-join list separator) - return a new Text of the Texts in a List with the separator
+/// between each
+fn text_join<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let separator = text_arg(mem, &args[1], "text-join")?;
+
+    let pieces = match *args[0].get(mem) {
+        Value::List(list) => {
+            let mut pieces = Vec::new();
+            for index in 0..list.length() {
+                pieces.push(text_arg(mem, list.read_ref(mem, index)?, "text-join")?);
+            }
+            pieces
+        }
+        Value::Nil => Vec::new(),
+        _ => return Err(err_eval("text-join expects a List of Text")),
+    };
+
+    text(mem, &pieces.join(&separator))
+}
+
+/// (text-upcase text) - return a new Text with every char converted to upper case
+fn text_upcase<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    text(mem, &text_arg(mem, &args[0], "text-upcase")?.to_uppercase())
+}
+
+/// (text-downcase text) - return a new Text with every char converted to lower case
+fn text_downcase<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    text(mem, &text_arg(mem, &args[0], "text-downcase")?.to_lowercase())
+}
+
+/// (text->symbol text) - return the Symbol with the Text as its name
+fn text_to_symbol<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    Ok(mem.lookup_sym(&text_arg(mem, &args[0], "text->symbol")?))
+}
+
+/// (symbol->text symbol) - return the name of a Symbol as a Text
+fn symbol_to_text<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let name = match *args[0].get(mem) {
+        Value::Symbol(s) => String::from(s.as_str(mem)),
+        _ => return Err(err_eval("symbol->text expects a Symbol")),
+    };
+
+    text(mem, &name)
+}
+
+/// (text->number text) - return the number a Text spells as a literal, or nil if it is not one
+fn text_to_number<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let value = text_arg(mem, &args[0], "text->number")?;
+
+    match Numbers::parse_number(mem, value.trim()) {
+        Some(number) => number,
+        None => Ok(mem.nil()),
+    }
+}
+
+/// (number->text number) - return a number printed as a Text that reads back as the same value
+fn number_to_text<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    match Numbers::number_to_string(mem, args[0].get(mem)) {
+        Some(value) => text(mem, &value),
+        None => Err(err_eval("number->text expects a number")),
+    }
+}
+
+/// (text-ref text index) - return the Char at a char index of a Text
+fn text_ref<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let index = index_arg(mem, &args[1], "text-ref")?;
+
+    match *args[0].get(mem) {
+        Value::Text(text) => match text.with_str(mem, |value| value.chars().nth(index)) {
+            Some(c) => Ok(mem.character(c)),
+            None => Err(RuntimeError::new(ErrorKind::BoundsError)),
+        },
+        _ => Err(err_eval("text-ref expects a Text")),
+    }
+}
+
+/// (char->integer char) - return the Unicode code point of a Char
+fn char_to_integer<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    number(mem, char_arg(mem, &args[0], "char->integer")? as usize)
+}
+
+/// (integer->char code-point) - return the Char with a Unicode code point
+fn integer_to_char<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let c = match *args[0].get(mem) {
+        Value::Number(n) => u32::try_from(n).ok().and_then(char::from_u32),
+        _ => None,
+    };
+
+    match c {
+        Some(c) => Ok(mem.character(c)),
+        None => Err(err_eval("integer->char expects a Unicode scalar value")),
+    }
+}
+
+/// (char-alphabetic? char) - return true if a Char is alphabetic
+fn char_is_alphabetic<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    Ok(mem.boolean(char_arg(mem, &args[0], "char-alphabetic?")?.is_alphabetic()))
+}
+
+/// (char-digit? char) - return true if a Char is a decimal digit 0-9
+fn char_is_digit<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    Ok(mem.boolean(char_arg(mem, &args[0], "char-digit?")?.is_ascii_digit()))
+}
+
+/// (char-whitespace? char) - return true if a Char is whitespace
+fn char_is_whitespace<'guard>(
+    mem: &'guard MutatorView,
+    args: &[TaggedCellPtr],
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    Ok(mem.boolean(char_arg(mem, &args[0], "char-whitespace?")?.is_whitespace()))
+}
@@ -2,7 +2,8 @@ use std::cell::Cell;
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
 use crate::evalrus::MutatorView::MutatorView;
-use crate::evalrus::Ptrs::{ScopedPtr, TaggedCellPtr, TaggedScopedPtr};
+use crate::evalrus::Ptrs::{ScopedPtr, TaggedCellPtr, TaggedPtr, TaggedScopedPtr};
+use crate::evalrus::Trace::{is_live, relocated, Trace, Tracer, Weak};
 use crate::evalrus::Traits::MutatorScope;
 use crate::evalrus::Value::Value;
 use crate::frontend::Array::{ArraySize, default_array_growth};
@@ -27,6 +28,9 @@ pub struct Dict {
     used_entries: Cell<ArraySize>,
     /// Backing array for key/value entries
     data: Cell<RawArray<DictItem>>,
+    /// Keys are held weakly: heap object keys are compared by identity, and an entry is removed
+    /// by the collector once its key is unreachable. Values are only kept alive by live keys.
+    weak_keys: bool,
 }
 
 impl Dict {
@@ -37,6 +41,20 @@ impl Dict {
         mem.alloc(Dict::new())
     }
 
+    /// Allocate a new instance with weakly held keys on the heap
+    pub fn alloc_weak<'guard>(
+        mem: &'guard MutatorView,
+    ) -> Result<ScopedPtr<'guard, Dict>, RuntimeError> {
+        let mut dict = Dict::new();
+        dict.weak_keys = true;
+        mem.alloc(dict)
+    }
+
+    /// Return true if the Dict holds its keys weakly
+    pub fn has_weak_keys(&self) -> bool {
+        self.weak_keys
+    }
+
     /// Allocate a new instance on the heap with pre-allocated capacity
     pub fn alloc_with_capacity<'guard>(
         mem: &'guard MutatorView,
@@ -65,8 +83,62 @@ impl Dict {
         }
 
         self.data.set(new_data);
+        mem.write_barrier(self);
         Ok(())
     }
+
+    /// Call `f` on every slot of the backing array, used or not
+    fn for_each_entry<F: FnMut(&mut DictItem)>(&self, mut f: F) {
+        let data = self.data.get();
+        if let Some(ptr) = data.as_ptr() {
+            for index in 0..data.capacity() {
+                f(unsafe { &mut *(ptr.offset(index as isize) as *mut DictItem) });
+            }
+        }
+    }
+
+    /// Recompute the hashes of heap object keys of a weak-keyed Dict after they have all been
+    /// given new addresses, as when loaded from an image
+    pub fn rehash_identity_keys(&self) {
+        if !self.weak_keys {
+            return;
+        }
+
+        self.for_each_entry(|entry| {
+            if let Some(hash) = identity_hash(entry.key.get_ptr()) {
+                entry.hash = hash;
+            }
+        });
+        self.rehash();
+    }
+
+    /// Reinsert every entry after the collector moved some keys, changing their identity hash
+    fn rehash(&self) {
+        let mut items = Vec::new();
+        self.for_each_entry(|entry| {
+            if !entry.key.is_nil() {
+                items.push(entry.clone());
+            }
+            *entry = DictItem::blank();
+        });
+
+        let data = self.data.get();
+        if let Some(ptr) = data.as_ptr() {
+            for item in items {
+                let mut index = (item.hash % data.capacity() as u64) as ArraySize;
+                loop {
+                    let entry = unsafe { &mut *(ptr.offset(index as isize) as *mut DictItem) };
+                    if entry.key.is_nil() {
+                        *entry = item;
+                        break;
+                    }
+                    index = (index + 1) % data.capacity();
+                }
+            }
+        }
+
+        self.used_entries.set(self.length.get());
+    }
 }
 
 impl Container<DictItem> for Dict {
@@ -75,6 +147,7 @@ impl Container<DictItem> for Dict {
             length: Cell::new(0),
             used_entries: Cell::new(0),
             data: Cell::new(RawArray::new()),
+            weak_keys: false,
         }
     }
 
@@ -86,6 +159,7 @@ impl Container<DictItem> for Dict {
             length: Cell::new(0),
             used_entries: Cell::new(0),
             data: Cell::new(RawArray::with_capacity(mem, capacity)?),
+            weak_keys: false,
         };
 
         let data = dict.data.get();
@@ -107,6 +181,72 @@ impl Container<DictItem> for Dict {
     }
 }
 
+impl Trace for DictItem {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.trace_tagged(&self.key);
+        tracer.trace_tagged(&self.value);
+    }
+}
+
+impl Trace for Dict {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.trace_array(&self.data);
+
+        if self.weak_keys {
+            tracer.trace_weak(self);
+        } else {
+            self.for_each_entry(|entry| entry.trace(tracer));
+        }
+    }
+}
+
+/// Weak-keyed Dict entries are ephemerons: the value is traced only once the key is known to
+/// be reachable from elsewhere.
+impl Weak for Dict {
+    fn trace_ephemerons(&self, tracer: &mut Tracer) {
+        self.for_each_entry(|entry| {
+            if !entry.key.is_nil() && is_live(entry.key.get_ptr()) {
+                tracer.trace_tagged(&entry.value);
+            }
+        });
+    }
+
+    fn trace_all_refs(&self, tracer: &mut Tracer) {
+        self.for_each_entry(|entry| entry.trace(tracer));
+    }
+
+    fn clear_dead(&self) {
+        let mut moved = false;
+
+        self.for_each_entry(|entry| {
+            if entry.key.is_nil() {
+                return;
+            }
+
+            let key = entry.key.get_ptr();
+            if !is_live(key) {
+                entry.key.set_to_nil();
+                entry.value.set_to_nil();
+                entry.hash = TOMBSTONE;
+                self.length.set(self.length.get() - 1);
+            } else {
+                let new_key = relocated(key);
+                if let Some(hash) = identity_hash(new_key) {
+                    if hash != entry.hash {
+                        entry.key.relocate(new_key);
+                        entry.hash = hash;
+                        moved = true;
+                    }
+                }
+            }
+        });
+
+        if moved {
+            self.rehash();
+        }
+    }
+}
+
 /// Hashable-indexed interface. Objects used as keys must implement Hashable.
 impl HashIndexedAnyContainer for Dict {
     fn lookup<'guard>(
@@ -114,7 +254,7 @@ impl HashIndexedAnyContainer for Dict {
         guard: &'guard dyn MutatorScope,
         key: TaggedScopedPtr,
     ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
-        let hash = hash_key(guard, key)?;
+        let hash = hash_key(guard, key, self.weak_keys)?;
         let data = self.data.get();
         let entry = find_entry(guard, &data, hash)?;
 
@@ -132,7 +272,7 @@ impl HashIndexedAnyContainer for Dict {
         key: TaggedScopedPtr<'guard>,
         value: TaggedScopedPtr<'guard>,
     ) -> Result<(), RuntimeError> {
-        let hash = hash_key(mem, key)?;
+        let hash = hash_key(mem, key, self.weak_keys)?;
 
         let mut data = self.data.get();
         // check the load factor (what percentage of the capacity is or has been used)
@@ -156,9 +296,16 @@ impl HashIndexedAnyContainer for Dict {
             }
         }
 
-        // finally, write the key, value and hash to the entry
-        entry.key.set(key);
-        entry.value.set(value);
+        // finally, write the key, value and hash to the entry. Weak entries must not be
+        // remembered individually or a minor collection would treat them as strong roots.
+        if self.weak_keys {
+            entry.key.store(key.get_ptr());
+            entry.value.store(value.get_ptr());
+            mem.write_barrier(self);
+        } else {
+            entry.key.set(mem, key);
+            entry.value.set(mem, value);
+        }
         entry.hash = hash;
 
         Ok(())
@@ -171,7 +318,7 @@ impl HashIndexedAnyContainer for Dict {
         guard: &'guard dyn MutatorScope,
         key: TaggedScopedPtr,
     ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
-        let hash = hash_key(guard, key)?;
+        let hash = hash_key(guard, key, self.weak_keys)?;
 
         let data = self.data.get();
         let entry = find_entry(guard, &data, hash)?;
@@ -198,7 +345,7 @@ impl HashIndexedAnyContainer for Dict {
         guard: &'guard dyn MutatorScope,
         key: TaggedScopedPtr,
     ) -> Result<bool, RuntimeError> {
-        let hash = hash_key(guard, key)?;
+        let hash = hash_key(guard, key, self.weak_keys)?;
         let data = self.data.get();
         let entry = find_entry(guard, &data, hash)?;
         Ok(!entry.key.is_nil())
@@ -275,10 +422,25 @@ fn needs_to_grow(used_entries: ArraySize, capacity: ArraySize) -> bool {
     ratio > LOAD_FACTOR
 }
 
+/// Hash a heap object key by address. The collector rehashes weak-keyed Dicts whose keys it
+/// moves.
+fn identity_hash(key: TaggedPtr) -> Option<u64> {
+    key.heap_object().map(|object| object.as_ptr() as u64)
+}
+
+/// Hash a key. Only Symbols and Numbers can be hashed by value; with `by_identity`, any heap
+/// object is hashed by address.
 fn hash_key<'guard>(
     guard: &'guard dyn MutatorScope,
     key: TaggedScopedPtr<'guard>,
+    by_identity: bool,
 ) -> Result<u64, RuntimeError> {
+    if by_identity {
+        if let Some(hash) = identity_hash(key.get_ptr()) {
+            return Ok(hash);
+        }
+    }
+
     match *key {
         Value::Symbol(s) => {
             // let mut hasher = FnvHasher::default();
@@ -287,6 +449,7 @@ fn hash_key<'guard>(
             Ok(hasher.finish())
         }
         Value::Number(n) => Ok(n as u64),
+        Value::Char(c) => Ok(c as u64),
         _ => Err(RuntimeError::new(ErrorKind::UnhashableError)),
     }
 }
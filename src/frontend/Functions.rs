@@ -3,6 +3,7 @@ use std::fmt;
 
 use crate::evalrus::MutatorView::MutatorView;
 use crate::evalrus::Ptrs::{CellPtr, ScopedPtr, TaggedCellPtr, TaggedScopedPtr};
+use crate::evalrus::Trace::{Trace, Tracer};
 use crate::evalrus::Traits::MutatorScope;
 use crate::evalrus::Value::Value;
 use crate::frontend::Array::{ArrayU16, List};
@@ -102,6 +103,14 @@ impl Function {
     }
 }
 
+impl Trace for Function {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.trace_tagged(&self.name);
+        tracer.trace_cell(&self.code);
+        tracer.trace_cell(&self.param_names);
+        tracer.trace_tagged(&self.nonlocal_refs);
+    }
+}
 
 /// A partial function application object type
 // ANCHOR: DefPartial
@@ -201,4 +210,10 @@ impl Partial {
     }
 }
 
-
+impl Trace for Partial {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.trace_cell(&self.args);
+        tracer.trace_tagged(&self.env);
+        tracer.trace_cell(&self.func);
+    }
+}
@@ -0,0 +1,761 @@
+use std::cmp::Ordering;
+use std::fmt;
+use crate::evalrus::MutatorView::MutatorView;
+use crate::evalrus::Ptrs::{TaggedPtr, TaggedScopedPtr, NUMBER_MAX, NUMBER_MIN};
+use crate::evalrus::Trace::{Trace, Tracer};
+use crate::evalrus::Traits::MutatorScope;
+use crate::evalrus::TypeList::TypeList;
+use crate::evalrus::Value::Value;
+use crate::frontend::Array::{ArraySize, ArrayU32};
+use crate::frontend::Traits::{Container, StackContainer};
+use crate::frontend::vm::err_eval;
+use crate::internals::Alloc::AllocObject;
+use crate::internals::Errors::{ErrorKind, RuntimeError};
+
+/// Powers of ten that fit in a u32 digit, used when converting to and from decimal
+const DECIMAL_CHUNK: u32 = 1_000_000_000;
+const DECIMAL_CHUNK_DIGITS: usize = 9;
+
+/// An integer too large to be held in a tagged pointer. Arithmetic produces one only when a
+/// result falls outside the tagged range; results that fit are always tagged numbers.
+pub struct NumberObject {
+    negative: bool,
+    /// Magnitude in base 2^32, least significant digit first, with no leading zero digits
+    digits: ArrayU32,
+}
+
+impl NumberObject {
+    /// Return the value as a host-side integer for arithmetic
+    fn to_integer(&self, guard: &dyn MutatorScope) -> Integer {
+        let digits = (0..self.digits.length())
+            .filter_map(|index| self.digits.read_ref(guard, index).ok().copied())
+            .collect();
+
+        Integer {
+            negative: self.negative,
+            digits,
+        }
+    }
+
+    /// Return the value in decimal
+    pub fn as_string(&self, guard: &dyn MutatorScope) -> String {
+        self.to_integer(guard).to_string()
+    }
+}
+
+impl AllocObject<TypeList> for NumberObject {
+    const TYPE_ID: TypeList = TypeList::NumberObject;
+}
+
+impl Trace for NumberObject {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.digits.trace(tracer);
+    }
+}
+
+/// A double-precision floating-point number
+pub struct Float {
+    value: f64,
+}
+
+impl Float {
+    /// Allocate a Float on the heap, returning a tagged pointer to it
+    pub fn alloc<'guard>(
+        mem: &'guard MutatorView,
+        value: f64,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        mem.alloc_tagged(Float { value })
+    }
+
+    pub fn value(&self) -> f64 {
+        self.value
+    }
+
+    /// Return the value in a form that parses back to the same value. Finite values always
+    /// include a `.` or an exponent so that they don't read back as integers.
+    pub fn as_string(&self) -> String {
+        if self.value.is_nan() {
+            String::from("+nan.0")
+        } else if self.value.is_infinite() {
+            String::from(if self.value > 0.0 { "+inf.0" } else { "-inf.0" })
+        } else {
+            format!("{:?}", self.value)
+        }
+    }
+}
+
+impl AllocObject<TypeList> for Float {
+    const TYPE_ID: TypeList = TypeList::Float;
+}
+
+/// An arbitrary-precision integer in host memory, used for the intermediate values of
+/// arithmetic on NumberObjects
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Integer {
+    negative: bool,
+    /// Magnitude in base 2^32, least significant digit first. Zero has no digits.
+    digits: Vec<u32>,
+}
+
+impl Integer {
+    fn from_isize(value: isize) -> Integer {
+        Integer::from_magnitude(value < 0, (value as i128).unsigned_abs())
+    }
+
+    fn from_magnitude(negative: bool, mut magnitude: u128) -> Integer {
+        let mut digits = Vec::new();
+
+        while magnitude > 0 {
+            digits.push(magnitude as u32);
+            magnitude >>= 32;
+        }
+
+        Integer { negative, digits }.normalized()
+    }
+
+    /// Return the value if it is within the tagged number range
+    fn to_tagged(&self) -> Option<isize> {
+        if self.digits.len() > 2 {
+            return None;
+        }
+
+        let magnitude = self
+            .digits
+            .iter()
+            .rev()
+            .fold(0i128, |acc, digit| (acc << 32) | *digit as i128);
+        let value = if self.negative { -magnitude } else { magnitude };
+
+        if value >= NUMBER_MIN as i128 && value <= NUMBER_MAX as i128 {
+            Some(value as isize)
+        } else {
+            None
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        self.digits.is_empty()
+    }
+
+    fn to_f64(&self) -> f64 {
+        let magnitude = self
+            .digits
+            .iter()
+            .rev()
+            .fold(0.0, |acc, digit| acc * 4294967296.0 + *digit as f64);
+
+        if self.negative {
+            -magnitude
+        } else {
+            magnitude
+        }
+    }
+
+    /// Remove leading zero digits, and the sign of zero
+    fn normalized(mut self) -> Integer {
+        while self.digits.last() == Some(&0) {
+            self.digits.pop();
+        }
+        if self.digits.is_empty() {
+            self.negative = false;
+        }
+        self
+    }
+
+    fn negated(mut self) -> Integer {
+        self.negative = !self.negative;
+        self.normalized()
+    }
+
+    fn add(&self, other: &Integer) -> Integer {
+        if self.negative == other.negative {
+            return Integer {
+                negative: self.negative,
+                digits: add_digits(&self.digits, &other.digits),
+            }
+            .normalized();
+        }
+
+        // opposite signs: subtract the smaller magnitude from the larger
+        match compare_digits(&self.digits, &other.digits) {
+            Ordering::Less => Integer {
+                negative: other.negative,
+                digits: sub_digits(&other.digits, &self.digits),
+            },
+            _ => Integer {
+                negative: self.negative,
+                digits: sub_digits(&self.digits, &other.digits),
+            },
+        }
+        .normalized()
+    }
+
+    fn compare(&self, other: &Integer) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => compare_digits(&self.digits, &other.digits),
+            (true, true) => compare_digits(&other.digits, &self.digits),
+        }
+    }
+
+    fn sub(&self, other: &Integer) -> Integer {
+        self.add(&other.clone().negated())
+    }
+
+    fn mul(&self, other: &Integer) -> Integer {
+        Integer {
+            negative: self.negative != other.negative,
+            digits: mul_digits(&self.digits, &other.digits),
+        }
+        .normalized()
+    }
+
+    /// Divide, truncating towards zero. The divisor must not be zero.
+    fn div(&self, other: &Integer) -> Integer {
+        Integer {
+            negative: self.negative != other.negative,
+            digits: div_digits(&self.digits, &other.digits),
+        }
+        .normalized()
+    }
+
+    /// Parse an optionally signed decimal integer
+    fn parse(text: &str) -> Option<Integer> {
+        let (negative, decimal) = match text.as_bytes().first() {
+            Some(b'-') => (true, &text[1..]),
+            Some(b'+') => (false, &text[1..]),
+            _ => (false, text),
+        };
+
+        if decimal.is_empty() || !decimal.bytes().all(|byte| byte.is_ascii_digit()) {
+            return None;
+        }
+
+        // consume the leading partial chunk first so the rest are whole chunks
+        let mut digits = Vec::new();
+        let first = decimal.len() % DECIMAL_CHUNK_DIGITS;
+        let mut start = 0;
+        let mut end = if first == 0 { DECIMAL_CHUNK_DIGITS } else { first };
+
+        while start < decimal.len() {
+            let chunk: u32 = decimal[start..end].parse().ok()?;
+            let scale = 10u32.pow((end - start) as u32);
+            mul_add_small(&mut digits, scale, chunk);
+
+            start = end;
+            end += DECIMAL_CHUNK_DIGITS;
+        }
+
+        Some(Integer { negative, digits }.normalized())
+    }
+}
+
+impl fmt::Display for Integer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+
+        let mut chunks = Vec::new();
+        let mut magnitude = self.digits.clone();
+        while !magnitude.is_empty() {
+            chunks.push(div_small(&mut magnitude, DECIMAL_CHUNK));
+        }
+
+        if self.negative {
+            write!(f, "-")?;
+        }
+        let mut chunks = chunks.iter().rev();
+        if let Some(most_significant) = chunks.next() {
+            write!(f, "{}", most_significant)?;
+        }
+        for chunk in chunks {
+            write!(f, "{:0width$}", chunk, width = DECIMAL_CHUNK_DIGITS)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn compare_digits(a: &[u32], b: &[u32]) -> Ordering {
+    a.len()
+        .cmp(&b.len())
+        .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_digits(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len().max(b.len()) + 1);
+    let mut carry = 0u64;
+
+    for index in 0..a.len().max(b.len()) {
+        let sum = *a.get(index).unwrap_or(&0) as u64 + *b.get(index).unwrap_or(&0) as u64 + carry;
+        result.push(sum as u32);
+        carry = sum >> 32;
+    }
+    if carry > 0 {
+        result.push(carry as u32);
+    }
+
+    result
+}
+
+/// Subtract magnitudes where `a` is at least `b`
+fn sub_digits(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+
+    for index in 0..a.len() {
+        let mut difference = a[index] as i64 - *b.get(index).unwrap_or(&0) as i64 - borrow;
+        borrow = 0;
+        if difference < 0 {
+            difference += 1 << 32;
+            borrow = 1;
+        }
+        result.push(difference as u32);
+    }
+
+    result
+}
+
+fn mul_digits(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = vec![0u32; a.len() + b.len()];
+
+    for (i, &x) in a.iter().enumerate() {
+        let mut carry = 0u64;
+        for (j, &y) in b.iter().enumerate() {
+            let product = x as u64 * y as u64 + result[i + j] as u64 + carry;
+            result[i + j] = product as u32;
+            carry = product >> 32;
+        }
+        result[i + b.len()] = carry as u32;
+    }
+
+    result
+}
+
+/// Multiply a magnitude by `scale` and add `addend`, in place
+fn mul_add_small(digits: &mut Vec<u32>, scale: u32, addend: u32) {
+    let mut carry = addend as u64;
+
+    for digit in digits.iter_mut() {
+        let product = *digit as u64 * scale as u64 + carry;
+        *digit = product as u32;
+        carry = product >> 32;
+    }
+    if carry > 0 {
+        digits.push(carry as u32);
+    }
+}
+
+/// Divide a magnitude by `divisor` in place, returning the remainder
+fn div_small(digits: &mut Vec<u32>, divisor: u32) -> u32 {
+    let mut remainder = 0u64;
+
+    for digit in digits.iter_mut().rev() {
+        let current = (remainder << 32) | *digit as u64;
+        *digit = (current / divisor as u64) as u32;
+        remainder = current % divisor as u64;
+    }
+    while digits.last() == Some(&0) {
+        digits.pop();
+    }
+
+    remainder as u32
+}
+
+/// Divide magnitudes by binary long division, discarding the remainder
+fn div_digits(a: &[u32], b: &[u32]) -> Vec<u32> {
+    if b.len() == 1 {
+        let mut quotient = a.to_vec();
+        div_small(&mut quotient, b[0]);
+        return quotient;
+    }
+
+    let mut quotient = vec![0u32; a.len()];
+    let mut remainder: Vec<u32> = Vec::new();
+
+    for bit in (0..a.len() * 32).rev() {
+        // shift the next bit of the dividend into the remainder
+        mul_add_small(&mut remainder, 2, (a[bit / 32] >> (bit % 32)) & 1);
+
+        if compare_digits(&remainder, b) != Ordering::Less {
+            remainder = sub_digits(&remainder, b);
+            while remainder.last() == Some(&0) {
+                remainder.pop();
+            }
+            quotient[bit / 32] |= 1 << (bit % 32);
+        }
+    }
+
+    quotient
+}
+
+/// Return the value of an integer as a host-side integer
+fn integer(guard: &dyn MutatorScope, value: TaggedScopedPtr) -> Result<Integer, RuntimeError> {
+    match *value {
+        Value::Number(n) => Ok(Integer::from_isize(n)),
+        Value::NumberObject(n) => Ok(n.to_integer(guard)),
+        _ => Err(err_eval("Expected a number")),
+    }
+}
+
+/// Return the value of a number of any type as a float
+fn float(guard: &dyn MutatorScope, value: TaggedScopedPtr) -> Result<f64, RuntimeError> {
+    match *value {
+        Value::Number(n) => Ok(n as f64),
+        Value::NumberObject(n) => Ok(n.to_integer(guard).to_f64()),
+        Value::Float(f) => Ok(f.value()),
+        _ => Err(err_eval("Expected a number")),
+    }
+}
+
+/// Return both operands as floats if either of them is a Float
+fn float_operands(
+    guard: &dyn MutatorScope,
+    a: TaggedScopedPtr,
+    b: TaggedScopedPtr,
+) -> Result<Option<(f64, f64)>, RuntimeError> {
+    match (*a, *b) {
+        (Value::Float(_), _) | (_, Value::Float(_)) => Ok(Some((float(guard, a)?, float(guard, b)?))),
+        _ => Ok(None),
+    }
+}
+
+/// Return an integer as a tagged number if it fits, otherwise as a new NumberObject
+fn from_integer<'guard>(
+    mem: &'guard MutatorView,
+    value: Integer,
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    if let Some(n) = value.to_tagged() {
+        return Ok(TaggedScopedPtr::new(mem, TaggedPtr::number(n)));
+    }
+
+    let digits = ArrayU32::with_capacity(mem, value.digits.len() as ArraySize)?;
+    for digit in &value.digits {
+        digits.push(mem, *digit)?;
+    }
+
+    mem.alloc_tagged(NumberObject {
+        negative: value.negative,
+        digits,
+    })
+}
+
+/// Return an unsigned integer, such as a count from the host, as a tagged number if it fits,
+/// otherwise as a new NumberObject
+pub fn from_unsigned<'guard>(
+    mem: &'guard MutatorView,
+    value: u128,
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    from_integer(mem, Integer::from_magnitude(false, value))
+}
+
+/// The implementations of an arithmetic operation for each representation of its operands
+struct Arithmetic {
+    /// Returns None on overflow
+    tagged: fn(isize, isize) -> Option<isize>,
+    float: fn(f64, f64) -> f64,
+    integer: fn(&Integer, &Integer) -> Integer,
+}
+
+/// Apply an operation to two numbers. Two tagged numbers give a tagged number if the result
+/// fits, any Float operand gives a Float, and otherwise the result is computed at full
+/// precision and demoted to a tagged number if it fits.
+fn arithmetic<'guard>(
+    mem: &'guard MutatorView,
+    a: TaggedScopedPtr<'guard>,
+    b: TaggedScopedPtr<'guard>,
+    op: Arithmetic,
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    if let (Value::Number(x), Value::Number(y)) = (*a, *b) {
+        if let Some(n) = (op.tagged)(x, y).filter(|n| *n >= NUMBER_MIN && *n <= NUMBER_MAX) {
+            return Ok(TaggedScopedPtr::new(mem, TaggedPtr::number(n)));
+        }
+    }
+
+    match float_operands(mem, a, b)? {
+        Some((x, y)) => Float::alloc(mem, (op.float)(x, y)),
+        None => from_integer(mem, (op.integer)(&integer(mem, a)?, &integer(mem, b)?)),
+    }
+}
+
+/// Add two numbers
+pub fn add<'guard>(
+    mem: &'guard MutatorView,
+    a: TaggedScopedPtr<'guard>,
+    b: TaggedScopedPtr<'guard>,
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    arithmetic(mem, a, b, Arithmetic {
+        tagged: isize::checked_add,
+        float: |x, y| x + y,
+        integer: Integer::add,
+    })
+}
+
+/// Subtract `b` from `a`
+pub fn subtract<'guard>(
+    mem: &'guard MutatorView,
+    a: TaggedScopedPtr<'guard>,
+    b: TaggedScopedPtr<'guard>,
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    arithmetic(mem, a, b, Arithmetic {
+        tagged: isize::checked_sub,
+        float: |x, y| x - y,
+        integer: Integer::sub,
+    })
+}
+
+/// Multiply two numbers
+pub fn multiply<'guard>(
+    mem: &'guard MutatorView,
+    a: TaggedScopedPtr<'guard>,
+    b: TaggedScopedPtr<'guard>,
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    arithmetic(mem, a, b, Arithmetic {
+        tagged: isize::checked_mul,
+        float: |x, y| x * y,
+        integer: Integer::mul,
+    })
+}
+
+/// Divide `num` by `denom`, truncating towards zero. Integer division by zero is an error;
+/// Float division by zero follows IEEE 754.
+pub fn divide_integer<'guard>(
+    mem: &'guard MutatorView,
+    num: TaggedScopedPtr<'guard>,
+    denom: TaggedScopedPtr<'guard>,
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    if float_operands(mem, num, denom)?.is_none() && integer(mem, denom)?.is_zero() {
+        return Err(err_eval("Division by zero"));
+    }
+
+    arithmetic(mem, num, denom, Arithmetic {
+        tagged: isize::checked_div,
+        float: |x, y| (x / y).trunc(),
+        integer: Integer::div,
+    })
+}
+
+/// Compare two numbers of any type. Returns None if either is NaN.
+pub fn compare(
+    guard: &dyn MutatorScope,
+    a: TaggedScopedPtr,
+    b: TaggedScopedPtr,
+) -> Result<Option<Ordering>, RuntimeError> {
+    if let (Value::Number(x), Value::Number(y)) = (*a, *b) {
+        return Ok(Some(x.cmp(&y)));
+    }
+
+    match float_operands(guard, a, b)? {
+        Some((x, y)) => Ok(x.partial_cmp(&y)),
+        None => Ok(Some(integer(guard, a)?.compare(&integer(guard, b)?))),
+    }
+}
+
+/// Return true if the text has the syntax of a float literal: an optionally signed decimal
+/// with a fraction, an exponent or both, or one of `+inf.0`, `-inf.0` and `+nan.0`
+fn is_float_literal(text: &str) -> bool {
+    if text == "+inf.0" || text == "-inf.0" || text == "+nan.0" {
+        return true;
+    }
+
+    let unsigned = text.strip_prefix(|c| c == '+' || c == '-').unwrap_or(text);
+    let (mantissa, exponent) = match unsigned.find(|c| c == 'e' || c == 'E') {
+        Some(index) => (&unsigned[..index], Some(&unsigned[index + 1..])),
+        None => (unsigned, None),
+    };
+    let (whole, fraction) = match mantissa.find('.') {
+        Some(index) => (&mantissa[..index], Some(&mantissa[index + 1..])),
+        None => (mantissa, None),
+    };
+
+    let digits = |part: &str| part.bytes().all(|byte| byte.is_ascii_digit());
+    let mantissa_ok = digits(whole)
+        && fraction.map_or(true, digits)
+        && !(whole.is_empty() && fraction.map_or(true, |f| f.is_empty()));
+    let exponent_ok = exponent.map_or(true, |e| {
+        let e = e.strip_prefix(|c| c == '+' || c == '-').unwrap_or(e);
+        !e.is_empty() && digits(e)
+    });
+
+    mantissa_ok && exponent_ok && (fraction.is_some() || exponent.is_some())
+}
+
+/// Parse a decimal integer literal of any size
+pub fn parse_integer<'guard>(
+    mem: &'guard MutatorView,
+    text: &str,
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    match Integer::parse(text) {
+        Some(value) => from_integer(mem, value),
+        None => Err(RuntimeError::new(ErrorKind::ParseError(format!(
+            "Invalid integer literal: {}",
+            text
+        )))),
+    }
+}
+
+/// Parse the text of a token as a number literal, integer or float. Returns None if the text
+/// does not have the syntax of a number, so that it can be read as a Symbol instead.
+pub fn parse_number<'guard>(
+    mem: &'guard MutatorView,
+    text: &str,
+) -> Option<Result<TaggedScopedPtr<'guard>, RuntimeError>> {
+    if Integer::parse(text).is_some() {
+        return Some(parse_integer(mem, text));
+    }
+
+    if is_float_literal(text) {
+        let value = match text {
+            "+inf.0" => f64::INFINITY,
+            "-inf.0" => f64::NEG_INFINITY,
+            "+nan.0" => f64::NAN,
+            _ => text.parse().ok()?,
+        };
+        return Some(Float::alloc(mem, value));
+    }
+
+    None
+}
+
+/// Return a number of any type in a form that parses back to the same value, or None if the
+/// value is not a number
+pub fn number_to_string(guard: &dyn MutatorScope, value: TaggedScopedPtr) -> Option<String> {
+    match *value {
+        Value::Number(n) => Some(n.to_string()),
+        Value::NumberObject(n) => Some(n.as_string(guard)),
+        Value::Float(f) => Some(f.as_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::evalrus::Heap::Heap;
+    use crate::evalrus::HeapConfig::HeapConfig;
+    use super::*;
+
+    fn to_digits(mut value: u128) -> Vec<u32> {
+        let mut digits = Vec::new();
+        while value > 0 {
+            digits.push(value as u32);
+            value >>= 32;
+        }
+        digits
+    }
+
+    fn from_digits(digits: &[u32]) -> u128 {
+        digits
+            .iter()
+            .rev()
+            .fold(0u128, |acc, digit| (acc << 32) | *digit as u128)
+    }
+
+    #[test]
+    fn div_digits_matches_native_division() {
+        let cases: [(u128, u128); 6] = [
+            (0, 3),
+            (100, 7),
+            ((1 << 100) + 12345, 10),
+            ((1 << 100) + 12345, (1 << 40) + 7),
+            (u128::MAX, (1 << 64) + 1),
+            (1 << 40, 1 << 70),
+        ];
+
+        for (a, b) in cases {
+            let quotient = div_digits(&to_digits(a), &to_digits(b));
+            assert_eq!(from_digits(&quotient), a / b, "{} / {}", a, b);
+        }
+    }
+
+    #[test]
+    fn div_truncates_towards_zero() {
+        let quotient = Integer::from_isize(-7).div(&Integer::from_isize(2));
+        assert_eq!(quotient, Integer::from_isize(-3));
+
+        let quotient = Integer::from_isize(7).div(&Integer::from_isize(-8));
+        assert_eq!(quotient, Integer::from_isize(0));
+    }
+
+    #[test]
+    fn parse_reads_decimal_integers() {
+        assert_eq!(Integer::parse("0"), Some(Integer::from_isize(0)));
+        assert_eq!(Integer::parse("-0"), Some(Integer::from_isize(0)));
+        assert_eq!(Integer::parse("+42"), Some(Integer::from_isize(42)));
+        assert_eq!(
+            Integer::parse("-4294967297"),
+            Some(Integer::from_isize(-4294967297))
+        );
+
+        let big = Integer::parse("340282366920938463463374607431768211455").unwrap();
+        assert_eq!(big.digits, to_digits(u128::MAX));
+        assert!(!big.negative);
+
+        for invalid in ["", "-", "+", "12a", "1.5", "1 2", "--1"] {
+            assert_eq!(Integer::parse(invalid), None, "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn display_prints_what_parse_reads() {
+        for text in [
+            "0",
+            "-1",
+            "4294967296",
+            "1000000000",
+            "-123456789012345678901234567890",
+            "100000000000000000000000000000000000001",
+        ] {
+            assert_eq!(Integer::parse(text).unwrap().to_string(), text);
+        }
+
+        assert_eq!(Integer::parse("+0007").unwrap().to_string(), "7");
+    }
+
+    #[test]
+    fn from_unsigned_promotes_values_too_large_to_tag() {
+        let heap = Heap::new(HeapConfig::default());
+        let mem = MutatorView { heap: &heap };
+
+        let largest_tagged = from_unsigned(&mem, NUMBER_MAX as u128).unwrap();
+        assert!(matches!(*largest_tagged, Value::Number(n) if n == NUMBER_MAX));
+
+        for value in [NUMBER_MAX as u128 + 1, u64::MAX as u128, u128::MAX] {
+            let number = from_unsigned(&mem, value).unwrap();
+            assert!(matches!(*number, Value::NumberObject(_)));
+            assert_eq!(number_to_string(&mem, number), Some(value.to_string()));
+        }
+    }
+
+    #[test]
+    fn float_literal_syntax() {
+        for valid in [
+            "1.5", "-0.25", "+3.", ".5", "1e10", "1E-3", "-2.5e+7", "+inf.0", "-inf.0", "+nan.0",
+        ] {
+            assert!(is_float_literal(valid), "{:?}", valid);
+        }
+
+        for invalid in [
+            "", ".", "1", "-12", "e5", "1e", "1e+", "1.2.3", "1.5x", "inf", "nan.0", "+-1.0",
+        ] {
+            assert!(!is_float_literal(invalid), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn float_printing_round_trips() {
+        for value in [0.0, -0.0, 1.0, -2.5, 0.1, 1e300, 5e-324, 123456789.125, f64::MAX] {
+            let text = Float { value }.as_string();
+            assert!(is_float_literal(&text), "{:?}", text);
+            assert!(Integer::parse(&text).is_none(), "{:?}", text);
+
+            let parsed: f64 = text.parse().unwrap();
+            assert_eq!(parsed.to_bits(), value.to_bits(), "{:?}", text);
+        }
+
+        assert_eq!(Float { value: f64::INFINITY }.as_string(), "+inf.0");
+        assert_eq!(Float { value: f64::NEG_INFINITY }.as_string(), "-inf.0");
+        assert_eq!(Float { value: f64::NAN }.as_string(), "+nan.0");
+    }
+}
@@ -39,5 +39,13 @@ impl<T: Sized> RawArray<T> {
             None => None,
         }
     }
+
+    /// Return a copy of the array pointing at backing storage moved by the collector
+    pub fn relocated(&self, ptr: NonNull<T>) -> RawArray<T> {
+        RawArray {
+            capacity: self.capacity,
+            ptr: Some(ptr),
+        }
+    }
 }
 j
\ No newline at end of file
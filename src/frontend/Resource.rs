@@ -0,0 +1,89 @@
+use std::any::Any;
+use std::cell::RefCell;
+use crate::evalrus::MutatorView::MutatorView;
+use crate::evalrus::Ptrs::{ScopedPtr, TaggedCellPtr};
+use crate::evalrus::Trace::{Trace, Tracer};
+use crate::evalrus::Traits::MutatorScope;
+use crate::evalrus::TypeList::TypeList;
+use crate::evalrus::Value::Value;
+use crate::frontend::vm::err_eval;
+use crate::internals::Alloc::AllocObject;
+use crate::internals::Errors::RuntimeError;
+
+/// Called with the payload of a Resource when it is closed or found unreachable
+pub type Finalizer = fn(Box<dyn Any>);
+
+/// A heap object wrapping a host-owned value such as a file handle. The heap runs the finalizer
+/// when the object dies, unless a script closed it first.
+pub struct Resource {
+    /// Symbol naming the kind of resource, for display
+    kind: TaggedCellPtr,
+    /// None once the resource has been closed
+    payload: RefCell<Option<Box<dyn Any>>>,
+    finalizer: Finalizer,
+}
+
+impl Resource {
+    /// Allocate a Resource on the heap, registering it for finalization
+    pub fn alloc<'guard>(
+        mem: &'guard MutatorView,
+        kind: &str,
+        payload: Box<dyn Any>,
+        finalizer: Finalizer,
+    ) -> Result<ScopedPtr<'guard, Resource>, RuntimeError> {
+        mem.alloc(Resource {
+            kind: TaggedCellPtr::new_with(mem.lookup_sym(kind)),
+            payload: RefCell::new(Some(payload)),
+            finalizer,
+        })
+    }
+
+    /// Return the kind of resource as a string slice
+    pub fn kind<'guard>(&self, guard: &'guard dyn MutatorScope) -> &'guard str {
+        match *self.kind.get(guard) {
+            Value::Symbol(s) => s.as_str(guard),
+            _ => "<resource>",
+        }
+    }
+
+    /// Return true if the resource has been closed
+    pub fn is_closed(&self) -> bool {
+        self.payload.borrow().is_none()
+    }
+
+    /// Call `f` with the payload, which must be of type T. Fails if the resource has been closed.
+    pub fn with<T: Any, R, F: FnOnce(&mut T) -> R>(&self, f: F) -> Result<R, RuntimeError> {
+        let mut payload = self.payload.borrow_mut();
+
+        match payload.as_mut() {
+            Some(payload) => match payload.downcast_mut::<T>() {
+                Some(value) => Ok(f(value)),
+                None => Err(err_eval("Resource payload is of a different type")),
+            },
+            None => Err(err_eval("Resource has been closed")),
+        }
+    }
+
+    /// Release the payload by running the finalizer. Returns false if it was already closed.
+    pub fn close(&self) -> bool {
+        let payload = self.payload.borrow_mut().take();
+
+        match payload {
+            Some(payload) => {
+                (self.finalizer)(payload);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl AllocObject<TypeList> for Resource {
+    const TYPE_ID: TypeList = TypeList::Resource;
+}
+
+impl Trace for Resource {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.trace_tagged(&self.kind);
+    }
+}
@@ -0,0 +1,150 @@
+use std::cell::Cell;
+use std::ptr::copy_nonoverlapping;
+use crate::evalrus::MutatorView::MutatorView;
+use crate::evalrus::Ptrs::ScopedPtr;
+use crate::evalrus::Trace::{Trace, Tracer};
+use crate::evalrus::Traits::MutatorScope;
+use crate::evalrus::TypeList::TypeList;
+use crate::frontend::Array::ArraySize;
+use crate::frontend::RawArray::RawArray;
+use crate::internals::Alloc::AllocObject;
+use crate::internals::Errors::{ErrorKind, RuntimeError};
+
+/// An immutable UTF-8 string. The bytes live in a separately allocated backing array which the
+/// collector may move, so the contents are only lent out for the duration of a closure or
+/// copied out.
+pub struct Text {
+    /// Length in bytes
+    length: ArraySize,
+    data: Cell<RawArray<u8>>,
+}
+
+impl Text {
+    /// Allocate a Text holding a copy of the given string
+    pub fn alloc<'guard>(
+        mem: &'guard MutatorView,
+        value: &str,
+    ) -> Result<ScopedPtr<'guard, Text>, RuntimeError> {
+        let length = ArraySize::try_from(value.len())
+            .map_err(|_| RuntimeError::new(ErrorKind::BadAllocationRequest))?;
+
+        let data = if length == 0 {
+            RawArray::new()
+        } else {
+            let data = RawArray::<u8>::with_capacity(mem, length)?;
+            if let Some(ptr) = data.as_ptr() {
+                unsafe { copy_nonoverlapping(value.as_ptr(), ptr as *mut u8, value.len()) }
+            }
+            data
+        };
+
+        mem.alloc(Text {
+            length,
+            data: Cell::new(data),
+        })
+    }
+
+    /// Call `f` with the contents as a string slice. The closure must not allocate: a collection
+    /// may move the bytes out from under the slice.
+    pub fn with_str<R, F: FnOnce(&str) -> R>(&self, _guard: &dyn MutatorScope, f: F) -> R {
+        match self.data.get().as_ptr() {
+            Some(ptr) => unsafe {
+                let slice = std::slice::from_raw_parts(ptr, self.length as usize);
+                f(std::str::from_utf8_unchecked(slice))
+            },
+            None => f(""),
+        }
+    }
+
+    /// Return a copy of the contents
+    pub fn to_string(&self, guard: &dyn MutatorScope) -> String {
+        self.with_str(guard, |value| String::from(value))
+    }
+
+    /// Return the length in chars
+    pub fn char_length(&self, guard: &dyn MutatorScope) -> usize {
+        self.with_str(guard, |value| value.chars().count())
+    }
+}
+
+impl AllocObject<TypeList> for Text {
+    const TYPE_ID: TypeList = TypeList::Text;
+}
+
+impl Trace for Text {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.trace_array(&self.data);
+    }
+}
+
+/// Return the byte offset of the char at `index`, or the byte length if `index` is the char
+/// length. Returns None if `index` is past the end.
+pub fn char_offset(value: &str, index: usize) -> Option<usize> {
+    value
+        .char_indices()
+        .map(|(offset, _)| offset)
+        .chain(std::iter::once(value.len()))
+        .nth(index)
+}
+
+/// Return the chars from `start` up to but excluding `end`
+pub fn substring(value: &str, start: usize, end: usize) -> Result<&str, RuntimeError> {
+    if start > end {
+        return Err(RuntimeError::new(ErrorKind::BoundsError));
+    }
+
+    let start_offset =
+        char_offset(value, start).ok_or(RuntimeError::new(ErrorKind::BoundsError))?;
+    let end_offset = char_offset(&value[start_offset..], end - start)
+        .ok_or(RuntimeError::new(ErrorKind::BoundsError))?;
+
+    Ok(&value[start_offset..start_offset + end_offset])
+}
+
+/// Return the char index of the first occurrence of `needle`
+pub fn find(value: &str, needle: &str) -> Option<usize> {
+    value
+        .find(needle)
+        .map(|offset| value[..offset].chars().count())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn char_offset_counts_chars_not_bytes() {
+        let value = "aé€😀b";
+
+        assert_eq!(char_offset(value, 0), Some(0));
+        assert_eq!(char_offset(value, 1), Some(1));
+        assert_eq!(char_offset(value, 2), Some(3));
+        assert_eq!(char_offset(value, 3), Some(6));
+        assert_eq!(char_offset(value, 4), Some(10));
+        assert_eq!(char_offset(value, 5), Some(value.len()));
+        assert_eq!(char_offset(value, 6), None);
+        assert_eq!(char_offset("", 0), Some(0));
+    }
+
+    #[test]
+    fn substring_takes_a_char_range() {
+        let value = "aé€😀b";
+
+        assert_eq!(substring(value, 0, 5).unwrap(), value);
+        assert_eq!(substring(value, 1, 4).unwrap(), "é€😀");
+        assert_eq!(substring(value, 3, 3).unwrap(), "");
+        assert_eq!(substring(value, 5, 5).unwrap(), "");
+    }
+
+    #[test]
+    fn substring_rejects_bad_ranges() {
+        let value = "aé€";
+
+        for (start, end) in [(2, 1), (0, 4), (4, 4)] {
+            match substring(value, start, end) {
+                Err(error) => assert_eq!(*error.error_kind(), ErrorKind::BoundsError),
+                Ok(text) => panic!("({}, {}) gave {:?}", start, end, text),
+            }
+        }
+    }
+}
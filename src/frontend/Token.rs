@@ -8,14 +8,14 @@ pub enum TokenType {
     Quote,
 }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct SourcePos {
     pub line: u32,
     pub column: u32,
 }
 
 impl SourcePos {
-    fn new(line: u32, column: u32) -> SourcePos {
+    pub fn new(line: u32, column: u32) -> SourcePos {
         SourcePos { line, column }
     }
 }
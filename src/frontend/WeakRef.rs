@@ -0,0 +1,59 @@
+use crate::evalrus::MutatorView::MutatorView;
+use crate::evalrus::Ptrs::{ScopedPtr, TaggedCellPtr, TaggedScopedPtr};
+use crate::evalrus::Trace::{is_live, relocated, Trace, Tracer, Weak};
+use crate::evalrus::Traits::MutatorScope;
+use crate::evalrus::TypeList::TypeList;
+use crate::internals::Alloc::AllocObject;
+use crate::internals::Errors::RuntimeError;
+
+/// A reference to an object that does not keep it alive. Once the target has been collected the
+/// reference reads as nil.
+pub struct WeakRef {
+    /// Written once on allocation, so never passes through the write barrier
+    target: TaggedCellPtr,
+}
+
+impl WeakRef {
+    /// Allocate a WeakRef to the given object on the heap
+    pub fn alloc<'guard>(
+        mem: &'guard MutatorView,
+        target: TaggedScopedPtr<'guard>,
+    ) -> Result<ScopedPtr<'guard, WeakRef>, RuntimeError> {
+        mem.alloc(WeakRef {
+            target: TaggedCellPtr::new_with(target),
+        })
+    }
+
+    /// Return the target object, or nil if it has been collected
+    pub fn get<'guard>(&self, guard: &'guard dyn MutatorScope) -> TaggedScopedPtr<'guard> {
+        self.target.get(guard)
+    }
+}
+
+impl AllocObject<TypeList> for WeakRef {
+    const TYPE_ID: TypeList = TypeList::WeakRef;
+}
+
+impl Trace for WeakRef {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.trace_weak(self);
+    }
+}
+
+impl Weak for WeakRef {
+    fn trace_ephemerons(&self, _tracer: &mut Tracer) {}
+
+    fn trace_all_refs(&self, tracer: &mut Tracer) {
+        tracer.trace_tagged(&self.target);
+    }
+
+    fn clear_dead(&self) {
+        let target = self.target.get_ptr();
+
+        if !is_live(target) {
+            self.target.set_to_nil();
+        } else {
+            self.target.relocate(relocated(target));
+        }
+    }
+}
@@ -8,3 +8,8 @@ pub mod Dicts;
 pub mod misc;
 pub mod vm;
 pub mod Functions;
+pub mod Numbers;
+pub mod Text;
+pub mod Builtins;
+pub mod WeakRef;
+pub mod Resource;
@@ -4,9 +4,11 @@ use std::future::join;
 use std::io::Bytes;
 use crate::evalrus::MutatorView::MutatorView;
 use crate::evalrus::Ptrs::{CellPtr, ScopedPtr, TaggedPtr, TaggedScopedPtr};
+use crate::evalrus::Trace::{Trace, Tracer};
 use crate::evalrus::Traits::MutatorScope;
-use crate::frontend::Array::{Array, ArraySize, List};
-use crate::frontend::Traits::{IndexedContainer, StackContainer};
+use crate::frontend::Array::{Array, ArraySize, ArrayU32, List};
+use crate::frontend::Token::SourcePos;
+use crate::frontend::Traits::{Container, IndexedContainer, StackContainer};
 use crate::internals::Errors::RuntimeError;
 
 type Register = u8;
@@ -28,6 +30,10 @@ pub const JUMP_UNKNOWN: i16 = 0x7fff;
 pub struct ByteCode {
     code: ArrayOpcode,
     literals: Literals,
+    /// Source positions as (instruction, line, column) triples in instruction order, recorded
+    /// only where `push_at()` changes the position. Empty unless the emitter calls `push_at()`,
+    /// which nothing in this tree does yet, so positions are currently unavailable.
+    positions: ArrayU32,
 }
 
 impl ByteCode {
@@ -38,6 +44,7 @@ impl ByteCode {
         mem.alloc(ByteCode {
             code: ArrayOpcode::new(),
             literals: Literals::new(),
+            positions: ArrayU32::new(),
         })
     }
 
@@ -46,6 +53,61 @@ impl ByteCode {
         self.code.push(mem, op)
     }
 
+    /// Append an instruction compiled from the given source position to the back of the
+    /// sequence. Instructions appended with `push()` after it are taken to share its position.
+    pub fn push_at<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        op: Opcode,
+        pos: SourcePos,
+    ) -> Result<(), RuntimeError> {
+        let instruction = self.code.length();
+        self.code.push(mem, op)?;
+
+        let entries = self.positions.length() / 3;
+        if entries > 0 {
+            let last = (entries - 1) * 3;
+            if self.positions.get(mem, last + 1)? == pos.line
+                && self.positions.get(mem, last + 2)? == pos.column
+            {
+                return Ok(());
+            }
+        }
+
+        self.positions.push(mem, instruction)?;
+        self.positions.push(mem, pos.line)?;
+        self.positions.push(mem, pos.column)
+    }
+
+    /// Return the source position the given instruction was compiled from, if known
+    pub fn source_pos<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        instruction: ArraySize,
+    ) -> Option<SourcePos> {
+        // binary search for the last entry at or before the instruction
+        let mut low = 0;
+        let mut high = self.positions.length() / 3;
+        while low < high {
+            let middle = (low + high) / 2;
+            if self.positions.get(guard, middle * 3).ok()? <= instruction {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+
+        if low == 0 {
+            return None;
+        }
+
+        let entry = (low - 1) * 3;
+        Some(SourcePos {
+            line: self.positions.get(guard, entry + 1).ok()?,
+            column: self.positions.get(guard, entry + 2).ok()?,
+        })
+    }
+
     /// Set the jump offset of an existing jump instruction to a new value
     pub fn update_jump_offset<'guard>(
         &self,
@@ -76,8 +138,7 @@ impl ByteCode {
         literal_id: LiteralId,
     ) -> Result<(), RuntimeError> {
         // TODO clone anything mutable
-        self.code
-            .push(mem, Opcode::LoadLiteral { dest, literal_id })
+        self.push(mem, Opcode::LoadLiteral { dest, literal_id })
     }
 
     /// Push a literal pointer/value to the back of the literals list and return it's index
@@ -102,6 +163,19 @@ impl ByteCode {
     }
 }
 
+impl Trace for ByteCode {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.code.trace(tracer);
+        self.literals.trace(tracer);
+        self.positions.trace(tracer);
+    }
+}
+
+/// Opcodes hold no pointers
+impl Trace for Opcode {
+    fn trace(&self, _tracer: &mut Tracer) {}
+}
+
 // impl printer::Print for ByteCode {
 //     fn print<'guard>(
 //         &self,
@@ -141,8 +215,13 @@ impl InstructionStream {
 
     /// Change to a different stack frame, either as a function call or a return
     // ANCHOR: DefInstructionStreamSwitchFrame
-    pub fn switch_frame(&self, code: ScopedPtr<'_, ByteCode>, ip: ArraySize) {
-        self.instructions.set(code);
+    pub fn switch_frame<'guard>(
+        &self,
+        guard: &'guard dyn MutatorScope,
+        code: ScopedPtr<'guard, ByteCode>,
+        ip: ArraySize,
+    ) {
+        self.instructions.set(guard, code);
         self.ip.set(ip);
     }
     // ANCHOR_END: DefInstructionStreamSwitchFrame
@@ -183,6 +262,12 @@ impl InstructionStream {
         self.ip.get()
     }
 
+    /// Return the source position of the instruction being executed, if known
+    pub fn current_pos<'guard>(&self, guard: &'guard dyn MutatorScope) -> Option<SourcePos> {
+        let ip = self.ip.get().checked_sub(1)?;
+        self.instructions.get(guard).source_pos(guard, ip)
+    }
+
     /// Adjust the instruction pointer by the given signed offset from the current ip
     pub fn jump(&self, offset: JumpOffset) {
         let mut ip = self.ip.get() as i32;
@@ -191,6 +276,12 @@ impl InstructionStream {
     }
 }
 
+impl Trace for InstructionStream {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.trace_cell(&self.instructions);
+    }
+}
+
 
 #[derive(PartialEq, Debug, Clone, Copy)]
 pub enum Opcode {
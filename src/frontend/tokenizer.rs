@@ -1,14 +1,407 @@
+use std::iter::Peekable;
+use std::slice::Iter;
 use crate::evalrus::MutatorView::MutatorView;
-use crate::evalrus::Ptrs::TaggedScopedPtr;
-use crate::frontend::Token::Token;
-use crate::internals::Errors::RuntimeError;
+use crate::evalrus::Pair::Pair;
+use crate::evalrus::Ptrs::{ScopedPtr, TaggedScopedPtr};
+use crate::frontend::Numbers;
+use crate::frontend::Text::Text;
+use crate::frontend::Token::{SourcePos, Token, TokenType};
+use crate::internals::Errors::{ErrorKind, RuntimeError};
 
-pub fn tokenize(input: &str) -> Result<Vec<Token>, RuntimeError>;
+// key characters
+const TAB: char = '\t';
+const SPACE: char = ' ';
+const CR: char = '\r';
+const LF: char = '\n';
+const OPEN_PAREN: char = '(';
+const CLOSE_PAREN: char = ')';
+const DOT: char = '.';
+const QUOTE: char = '\'';
+const DOUBLE_QUOTE: char = '"';
+const BACKSLASH: char = '\\';
+const COMMENT: char = ';';
 
+fn err_lexer(pos: SourcePos, reason: &str) -> RuntimeError {
+    RuntimeError::with_pos(ErrorKind::LexerError(String::from(reason)), pos)
+}
+
+fn err_parser(pos: SourcePos, reason: &str) -> RuntimeError {
+    RuntimeError::with_pos(ErrorKind::ParseError(String::from(reason)), pos)
+}
+
+/// Return true if the char ends a symbol
+fn is_terminating(c: char) -> bool {
+    match c {
+        TAB | SPACE | CR | LF | OPEN_PAREN | CLOSE_PAREN | COMMENT | DOUBLE_QUOTE => true,
+        _ => false,
+    }
+}
+
+/// Split source text into tokens. Lines count from 1 and columns from 0.
+pub fn tokenize(input: &str) -> Result<Vec<Token>, RuntimeError> {
+    let mut tokens = Vec::new();
+
+    let mut line = 1;
+    let mut column = 0;
+
+    let mut chars = input.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let pos = SourcePos::new(line, column);
+        column += 1;
+
+        match c {
+            LF => {
+                line += 1;
+                column = 0;
+            }
+
+            TAB | SPACE | CR => (),
+
+            COMMENT => {
+                while let Some(&next) = chars.peek() {
+                    if next == LF {
+                        break;
+                    }
+                    chars.next();
+                    column += 1;
+                }
+            }
+
+            OPEN_PAREN => tokens.push(Token {
+                pos,
+                token: TokenType::OpenParen,
+            }),
+
+            CLOSE_PAREN => tokens.push(Token {
+                pos,
+                token: TokenType::CloseParen,
+            }),
+
+            QUOTE => tokens.push(Token {
+                pos,
+                token: TokenType::Quote,
+            }),
+
+            DOUBLE_QUOTE => {
+                let mut text = String::new();
+
+                loop {
+                    let escape_pos = SourcePos::new(line, column);
+                    let next = match chars.next() {
+                        Some(next) => next,
+                        None => return Err(err_lexer(pos, "Unterminated text literal")),
+                    };
+                    column += 1;
+
+                    match next {
+                        DOUBLE_QUOTE => break,
+
+                        BACKSLASH => {
+                            let escaped = match chars.next() {
+                                Some('n') => LF,
+                                Some('t') => TAB,
+                                Some('r') => CR,
+                                Some(BACKSLASH) => BACKSLASH,
+                                Some(DOUBLE_QUOTE) => DOUBLE_QUOTE,
+                                Some(_) => return Err(err_lexer(escape_pos, "Unknown escape")),
+                                None => return Err(err_lexer(pos, "Unterminated text literal")),
+                            };
+                            column += 1;
+                            text.push(escaped);
+                        }
+
+                        LF => {
+                            line += 1;
+                            column = 0;
+                            text.push(LF);
+                        }
+
+                        _ => text.push(next),
+                    }
+                }
+
+                tokens.push(Token {
+                    pos,
+                    token: TokenType::Text(text),
+                });
+            }
+
+            // a dot on its own; `.5` is read as a symbol below
+            DOT if chars.peek().map_or(true, |&next| is_terminating(next)) => tokens.push(Token {
+                pos,
+                token: TokenType::Dot,
+            }),
+
+            _ => {
+                let mut symbol = String::from(c);
+
+                // the char of a char literal is taken even if it would end a symbol, as in `#\(`
+                if c == '#' && chars.peek() == Some(&BACKSLASH) {
+                    symbol.push(BACKSLASH);
+                    chars.next();
+                    column += 1;
+
+                    if let Some(literal) = chars.next() {
+                        symbol.push(literal);
+                        column += 1;
+                        if literal == LF {
+                            line += 1;
+                            column = 0;
+                        }
+                    }
+                }
+
+                while let Some(&next) = chars.peek() {
+                    if is_terminating(next) {
+                        break;
+                    }
+                    symbol.push(next);
+                    chars.next();
+                    column += 1;
+                }
+
+                tokens.push(Token {
+                    pos,
+                    token: TokenType::Symbol(symbol),
+                });
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Return the value of a boolean literal, `#t` or `true` and `#f` or `false`, or None if the
+/// symbol text is not one
+pub fn parse_boolean(text: &str) -> Option<bool> {
+    match text {
+        "#t" | "true" => Some(true),
+        "#f" | "false" => Some(false),
+        _ => None,
+    }
+}
+
+/// Return the value of a char literal, or None if the symbol text is not one. A literal is `#\`
+/// followed by the char itself, a name such as `space`, or `x` and a hexadecimal code point.
+pub fn parse_char(text: &str) -> Option<char> {
+    let rest = text.strip_prefix("#\\")?;
+
+    let mut chars = rest.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Some(c);
+    }
+
+    match rest {
+        "space" => Some(' '),
+        "newline" => Some('\n'),
+        "tab" => Some('\t'),
+        "return" => Some('\r'),
+        "null" => Some('\0'),
+        _ => {
+            let hex = rest.strip_prefix('x')?;
+            if !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+                return None;
+            }
+            char::from_u32(u32::from_str_radix(hex, 16).ok()?)
+        }
+    }
+}
+
+/// Read symbol text as the literal it spells, or as a Symbol if it spells none
+fn parse_atom<'guard>(
+    mem: &'guard MutatorView,
+    text: &str,
+    pos: SourcePos,
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    if let Some(value) = parse_boolean(text) {
+        return Ok(mem.boolean(value));
+    }
+
+    if text.starts_with("#\\") {
+        return match parse_char(text) {
+            Some(value) => Ok(mem.character(value)),
+            None => Err(err_parser(pos, "Invalid char literal")),
+        };
+    }
+
+    match Numbers::parse_number(mem, text) {
+        Some(number) => number,
+        None => Ok(mem.lookup_sym(text)),
+    }
+}
+
+type Tokens<'t> = Peekable<Iter<'t, Token>>;
+
+/// A list under construction, recording the source position of each value in it
+struct PairList<'guard> {
+    head: Option<TaggedScopedPtr<'guard>>,
+    tail: Option<ScopedPtr<'guard, Pair>>,
+}
+
+impl<'guard> PairList<'guard> {
+    fn new() -> PairList<'guard> {
+        PairList {
+            head: None,
+            tail: None,
+        }
+    }
+
+    /// Append a value to the end of the list
+    fn push(
+        &mut self,
+        mem: &'guard MutatorView,
+        value: TaggedScopedPtr<'guard>,
+        pos: SourcePos,
+    ) -> Result<(), RuntimeError> {
+        let pair = Pair::new();
+        pair.first.set(mem, value);
+        pair.first_pos.set(Some(pos));
+
+        let pair = mem.alloc(pair)?;
+
+        match self.tail {
+            Some(tail) => {
+                tail.second.set(mem, pair.as_tagged(mem));
+                tail.second_pos.set(Some(pos));
+            }
+            None => self.head = Some(pair.as_tagged(mem)),
+        }
+
+        self.tail = Some(pair);
+        Ok(())
+    }
+
+    /// Set the second value of the last pair
+    fn dot(&self, mem: &'guard MutatorView, value: TaggedScopedPtr<'guard>, pos: SourcePos) {
+        if let Some(tail) = self.tail {
+            tail.dot(mem, value);
+            tail.second_pos.set(Some(pos));
+        }
+    }
+
+    /// Return the head of the list, or nil if nothing was pushed
+    fn get(self, mem: &'guard MutatorView) -> TaggedScopedPtr<'guard> {
+        match self.head {
+            Some(head) => head,
+            None => mem.nil(),
+        }
+    }
+}
+
+/// Read the rest of a list after its open paren
+fn parse_list<'guard>(
+    mem: &'guard MutatorView,
+    tokens: &mut Tokens,
+    open: SourcePos,
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let mut list = PairList::new();
+    let mut empty = true;
+
+    loop {
+        match tokens.peek() {
+            None => return Err(err_parser(open, "Unterminated list")),
+
+            Some(Token {
+                token: TokenType::CloseParen,
+                ..
+            }) => {
+                tokens.next();
+                return Ok(list.get(mem));
+            }
+
+            Some(Token {
+                token: TokenType::Dot,
+                pos,
+            }) => {
+                let pos = *pos;
+                if empty {
+                    return Err(err_parser(pos, "Dot before any list value"));
+                }
+                tokens.next();
+
+                let value_pos = match tokens.peek() {
+                    Some(token) => token.pos,
+                    None => return Err(err_parser(open, "Unterminated list")),
+                };
+                let value = parse_sexpr(mem, tokens)?;
+                list.dot(mem, value, value_pos);
+
+                return match tokens.next() {
+                    Some(Token {
+                        token: TokenType::CloseParen,
+                        ..
+                    }) => Ok(list.get(mem)),
+                    Some(token) => Err(err_parser(token.pos, "Expected ')' after dotted value")),
+                    None => Err(err_parser(open, "Unterminated list")),
+                };
+            }
+
+            Some(token) => {
+                let pos = token.pos;
+                let value = parse_sexpr(mem, tokens)?;
+                list.push(mem, value, pos)?;
+                empty = false;
+            }
+        }
+    }
+}
+
+/// Read one expression from the tokens
+fn parse_sexpr<'guard>(
+    mem: &'guard MutatorView,
+    tokens: &mut Tokens,
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let token = match tokens.next() {
+        Some(token) => token,
+        None => {
+            return Err(RuntimeError::new(ErrorKind::ParseError(String::from(
+                "Unexpected end of input",
+            ))))
+        }
+    };
+
+    match token.token {
+        TokenType::OpenParen => parse_list(mem, tokens, token.pos),
+
+        TokenType::Symbol(ref text) => parse_atom(mem, text, token.pos),
+
+        // 'x is read as (quote x)
+        TokenType::Quote => {
+            let value_pos = match tokens.peek() {
+                Some(next) => next.pos,
+                None => return Err(err_parser(token.pos, "Nothing to quote")),
+            };
+            let value = parse_sexpr(mem, tokens)?;
+
+            let mut list = PairList::new();
+            list.push(mem, mem.lookup_sym("quote"), token.pos)?;
+            list.push(mem, value, value_pos)?;
+            Ok(list.get(mem))
+        }
+
+        TokenType::CloseParen => Err(err_parser(token.pos, "Unmatched ')'")),
+
+        TokenType::Dot => Err(err_parser(token.pos, "Dot outside of a list")),
+
+        TokenType::Text(ref text) => Ok(Text::alloc(mem, text)?.as_tagged(mem)),
+    }
+}
+
+/// Read exactly one expression from the tokens
 fn parse_tokens<'guard>(
     mem: &'guard MutatorView,
     tokens: Vec<Token>,
-) -> Result<TaggedScopedPtr<'guard>, RuntimeError>;
+) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+    let mut tokens = tokens.iter().peekable();
+
+    let value = parse_sexpr(mem, &mut tokens)?;
+
+    match tokens.next() {
+        Some(token) => Err(err_parser(token.pos, "Unexpected input after expression")),
+        None => Ok(value),
+    }
+}
 
 pub fn parse<'guard>(
     mem: &'guard MutatorView,
@@ -18,3 +411,214 @@ pub fn parse<'guard>(
 }
 
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evalrus::Heap::Heap;
+    use crate::evalrus::HeapConfig::HeapConfig;
+    use crate::evalrus::Value::Value;
+
+    #[test]
+    fn tokenize_positions() {
+        let tokens = tokenize("(a . b)\n 'cd ; comment\n.5").unwrap();
+
+        let expected = [
+            (1, 0, TokenType::OpenParen),
+            (1, 1, TokenType::Symbol(String::from("a"))),
+            (1, 3, TokenType::Dot),
+            (1, 5, TokenType::Symbol(String::from("b"))),
+            (1, 6, TokenType::CloseParen),
+            (2, 1, TokenType::Quote),
+            (2, 2, TokenType::Symbol(String::from("cd"))),
+            (3, 0, TokenType::Symbol(String::from(".5"))),
+        ];
+
+        assert_eq!(tokens.len(), expected.len());
+        for (token, (line, column, token_type)) in tokens.into_iter().zip(expected) {
+            assert_eq!(token.pos, SourcePos::new(line, column));
+            assert_eq!(token.token, token_type);
+        }
+    }
+
+    fn is_symbol(mem: &MutatorView, value: TaggedScopedPtr, name: &str) -> bool {
+        matches!(*value, Value::Symbol(symbol) if symbol.as_str(mem) == name)
+    }
+
+    #[test]
+    fn parse_lists_and_symbols() {
+        let heap = Heap::new(HeapConfig::default());
+        let mem = MutatorView { heap: &heap };
+
+        let list = parse(&mem, "(a (b) . c)").unwrap();
+        match *list {
+            Value::Pair(pair) => {
+                assert!(is_symbol(&mem, pair.first.get(&mem), "a"));
+                assert_eq!(pair.first_pos.get(), Some(SourcePos::new(1, 1)));
+            }
+            _ => panic!("expected a list"),
+        }
+
+        assert!(matches!(*parse(&mem, "()").unwrap(), Value::Nil));
+
+        let quoted = parse(&mem, "'a").unwrap();
+        match *quoted {
+            Value::Pair(pair) => assert!(is_symbol(&mem, pair.first.get(&mem), "quote")),
+            _ => panic!("expected (quote a)"),
+        }
+
+        for invalid in ["", "(a", ")", "(. a)", "(a . b c)", "a b"] {
+            assert!(parse(&mem, invalid).is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn parse_promotes_oversized_integer_literals() {
+        let heap = Heap::new(HeapConfig::default());
+        let mem = MutatorView { heap: &heap };
+
+        assert!(matches!(*parse(&mem, "42").unwrap(), Value::Number(42)));
+        assert!(matches!(*parse(&mem, "-7").unwrap(), Value::Number(-7)));
+
+        for literal in [
+            "123456789012345678901234567890",
+            "-123456789012345678901234567890",
+        ] {
+            let value = parse(&mem, literal).unwrap();
+            assert!(matches!(*value, Value::NumberObject(_)), "{:?}", literal);
+            assert_eq!(Numbers::number_to_string(&mem, value).as_deref(), Some(literal));
+        }
+
+        let list = parse(&mem, "(1 99999999999999999999999)").unwrap();
+        match *list {
+            Value::Pair(pair) => {
+                assert!(matches!(*pair.first.get(&mem), Value::Number(1)));
+                match *pair.second.get(&mem) {
+                    Value::Pair(rest) => {
+                        assert!(matches!(*rest.first.get(&mem), Value::NumberObject(_)))
+                    }
+                    _ => panic!("expected a second pair"),
+                }
+            }
+            _ => panic!("expected a list"),
+        }
+    }
+
+    #[test]
+    fn parse_float_literals() {
+        let heap = Heap::new(HeapConfig::default());
+        let mem = MutatorView { heap: &heap };
+
+        for literal in ["1.5", "-0.25", ".5", "1e10", "+inf.0", "-inf.0", "+nan.0"] {
+            let value = parse(&mem, literal).unwrap();
+            assert!(matches!(*value, Value::Float(_)), "{:?}", literal);
+        }
+
+        let value = parse(&mem, "1.5").unwrap();
+        assert_eq!(Numbers::number_to_string(&mem, value).as_deref(), Some("1.5"));
+
+        // a dot followed by a terminating char is still a dotted pair
+        let pair = parse(&mem, "(1.5 . .5)").unwrap();
+        match *pair {
+            Value::Pair(pair) => {
+                assert!(matches!(*pair.first.get(&mem), Value::Float(_)));
+                assert!(matches!(*pair.second.get(&mem), Value::Float(_)));
+            }
+            _ => panic!("expected a pair"),
+        }
+
+        for symbol in ["1.2.3", "e5", "inf"] {
+            assert!(matches!(*parse(&mem, symbol).unwrap(), Value::Symbol(_)), "{:?}", symbol);
+        }
+    }
+
+    #[test]
+    fn tokenize_text_literals() {
+        let tokens = tokenize("(f \"a \\\"b\\\"\\n\" \"two\nlines\")").unwrap();
+
+        assert_eq!(tokens[2].token, TokenType::Text(String::from("a \"b\"\n")));
+        assert_eq!(tokens[2].pos, SourcePos::new(1, 3));
+        assert_eq!(tokens[3].token, TokenType::Text(String::from("two\nlines")));
+        assert_eq!(tokens[4].pos, SourcePos::new(2, 6));
+
+        let error = tokenize("(f\n \"abc").unwrap_err();
+        assert_eq!(error.error_pos(), Some(SourcePos::new(2, 1)));
+        assert!(tokenize("\"\\q\"").is_err());
+    }
+
+    #[test]
+    fn parse_text_literals() {
+        let heap = Heap::new(HeapConfig::default());
+        let mem = MutatorView { heap: &heap };
+
+        match *parse(&mem, "\"hello, λ\"").unwrap() {
+            Value::Text(text) => assert_eq!(text.to_string(&mem), "hello, λ"),
+            _ => panic!("expected a Text"),
+        }
+    }
+
+    #[test]
+    fn parse_boolean_literals() {
+        let heap = Heap::new(HeapConfig::default());
+        let mem = MutatorView { heap: &heap };
+
+        for (literal, expected) in [("#t", true), ("true", true), ("#f", false), ("false", false)] {
+            let value = parse(&mem, literal).unwrap();
+            assert!(matches!(*value, Value::Boolean(b) if b == expected), "{:?}", literal);
+        }
+
+        assert!(matches!(*parse(&mem, "truth").unwrap(), Value::Symbol(_)));
+    }
+
+    #[test]
+    fn parse_char_literals() {
+        let heap = Heap::new(HeapConfig::default());
+        let mem = MutatorView { heap: &heap };
+
+        let list = parse(&mem, "(#\\( #\\  #\\) #\\space #\\x41)").unwrap();
+        let mut expected = ['(', ' ', ')', ' ', 'A'].into_iter();
+        let mut rest = list;
+        while let Value::Pair(pair) = *rest {
+            let value = pair.first.get(&mem);
+            let c = expected.next().unwrap();
+            assert!(matches!(*value, Value::Char(v) if v == c), "{:?}", c);
+            rest = pair.second.get(&mem);
+        }
+        assert_eq!(expected.next(), None);
+
+        match parse(&mem, "(a #\\bogus)") {
+            Err(error) => assert_eq!(error.error_pos(), Some(SourcePos::new(1, 3))),
+            Ok(_) => panic!("expected an invalid char literal"),
+        }
+    }
+
+    #[test]
+    fn parse_char_reads_single_chars() {
+        assert_eq!(parse_char("#\\a"), Some('a'));
+        assert_eq!(parse_char("#\\("), Some('('));
+        assert_eq!(parse_char("#\\x"), Some('x'));
+        assert_eq!(parse_char("#\\λ"), Some('λ'));
+        assert_eq!(parse_char("#\\😀"), Some('😀'));
+    }
+
+    #[test]
+    fn parse_char_reads_names_and_code_points() {
+        assert_eq!(parse_char("#\\space"), Some(' '));
+        assert_eq!(parse_char("#\\newline"), Some('\n'));
+        assert_eq!(parse_char("#\\tab"), Some('\t'));
+        assert_eq!(parse_char("#\\return"), Some('\r'));
+        assert_eq!(parse_char("#\\null"), Some('\0'));
+        assert_eq!(parse_char("#\\x41"), Some('A'));
+        assert_eq!(parse_char("#\\x3bb"), Some('λ'));
+        assert_eq!(parse_char("#\\x1F600"), Some('😀'));
+    }
+
+    #[test]
+    fn parse_char_rejects_other_text() {
+        for invalid in [
+            "a", "#\\", "\\a", "#a", "#\\ab", "#\\Space", "#\\xzz", "#\\x+41", "#\\xD800",
+            "#\\x110000",
+        ] {
+            assert_eq!(parse_char(invalid), None, "{:?}", invalid);
+        }
+    }
+}
@@ -1,16 +1,20 @@
 use std::cell::Cell;
 use std::error::Error;
+use crate::evalrus::AllocProfile::{AllocSite, CallStack};
 use crate::evalrus::MutatorView::MutatorView;
 use crate::evalrus::Pair::Pair;
 
 use crate::evalrus::Ptrs::{CellPtr, ScopedPtr, TaggedCellPtr, TaggedPtr, TaggedScopedPtr};
+use crate::evalrus::Trace::{Trace, Tracer};
 use crate::evalrus::Traits::MutatorScope;
 use crate::evalrus::Value::Value;
 use crate::frontend::Array::{Array, ArraySize, List};
+use crate::frontend::Builtins;
 use crate::frontend::Dicts::Dict;
 use crate::frontend::Functions::{Function, Partial};
+use crate::frontend::Numbers;
 use crate::frontend::opcodes::{ByteCode, InstructionStream, Opcode};
-use crate::frontend::Traits::IndexedContainer;
+use crate::frontend::Traits::{Container, IndexedContainer};
 use crate::internals::Errors::RuntimeError;
 
 pub const RETURN_REG: usize = 0;
@@ -75,6 +79,12 @@ impl CallFrame {
     }
 }
 
+impl Trace for CallFrame {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.trace_cell(&self.function);
+    }
+}
+
 /// Call frames are stored in a separate stack to the register window stack. This simplifies types
 /// and stack math.
 // ANCHOR: DefCallFrameList
@@ -132,7 +142,7 @@ impl Upvalue {
         ptr: TaggedPtr,
     ) -> Result<(), RuntimeError> {
         match self.closed.get() {
-            true => self.value.set_to_ptr(ptr),
+            true => self.value.set_to_ptr(guard, ptr),
             false => {
                 IndexedContainer::set(&*stack, guard, self.location, TaggedCellPtr::new_ptr(ptr))?
             }
@@ -147,12 +157,18 @@ impl Upvalue {
         stack: ScopedPtr<'guard, List>,
     ) -> Result<(), RuntimeError> {
         let ptr = IndexedContainer::get(&*stack, guard, self.location)?.get_ptr();
-        self.value.set_to_ptr(ptr);
+        self.value.set_to_ptr(guard, ptr);
         self.closed.set(true);
         Ok(())
     }
 }
 
+impl Trace for Upvalue {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.trace_tagged(&self.value);
+    }
+}
+
 struct IndexedAnyContainer<T>(&T, &'guard dyn MutatorScope, ArraySize);
 
 /// Get the Upvalue for the index into the given closure environment.
@@ -197,6 +213,36 @@ pub struct Thread {
 }
 // ANCHOR_END: DefThread
 
+/// The Thread is the root of everything the VM can reach: registers, call frames, globals and
+/// open upvalues.
+impl Trace for Thread {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.trace_cell(&self.frames);
+        tracer.trace_cell(&self.stack);
+        tracer.trace_cell(&self.upvalues);
+        tracer.trace_cell(&self.globals);
+        tracer.trace_cell(&self.instr);
+    }
+}
+
+/// Allocations are attributed to the functions on the Thread's call frames and the position of
+/// the instruction being executed
+impl CallStack for Thread {
+    fn alloc_site(&self, guard: &dyn MutatorScope) -> AllocSite {
+        let frames = self.frames.get(guard);
+
+        let names = (0..frames.length())
+            .filter_map(|index| frames.read_ref(guard, index).ok())
+            .map(|frame| String::from(frame.function.get(guard).name(guard)))
+            .collect();
+
+        AllocSite {
+            frames: names,
+            pos: self.instr.get(guard).current_pos(guard),
+        }
+    }
+}
+
 struct StackAnyContainer<T>(&T, &'guard MutatorView<'memory>, T);
 
 impl Thread {
@@ -204,6 +250,19 @@ impl Thread {
     /// bytecode yet.
     pub fn alloc<'guard>(
         mem: &'guard MutatorView,
+    ) -> Result<ScopedPtr<'guard, Thread>, RuntimeError> {
+        // create a globals dict with the builtins bound
+        let globals = Dict::alloc(mem)?;
+        Builtins::install(mem, globals)?;
+
+        Thread::alloc_with_globals(mem, globals)
+    }
+
+    /// Allocate a new Thread sharing an existing globals Dict, such as one loaded from a heap
+    /// image
+    pub fn alloc_with_globals<'guard>(
+        mem: &'guard MutatorView,
+        globals: ScopedPtr<'guard, Dict>,
     ) -> Result<ScopedPtr<'guard, Thread>, RuntimeError> {
         // create an empty stack frame array
         let frames = CallFrameList::alloc_with_capacity(mem, 16)?;
@@ -215,9 +274,6 @@ impl Thread {
         // create an empty upvalue stack->heap mapping
         let upvalues = Dict::alloc(mem)?;
 
-        // create an empty globals dict
-        let globals = Dict::alloc(mem)?;
-
         // create an empty instruction stream
         let blank_code = ByteCode::alloc(mem)?;
         let instr = InstructionStream::alloc(mem, blank_code)?;
@@ -232,6 +288,11 @@ impl Thread {
         })
     }
 
+    /// Return the Thread's globals Dict
+    pub fn globals<'guard>(&self, guard: &'guard dyn MutatorScope) -> ScopedPtr<'guard, Dict> {
+        self.globals.get(guard)
+    }
+
     /// Retrieve an Upvalue for the given absolute stack offset.
     fn upvalue_lookup<'guard>(
         &self,
@@ -291,7 +352,9 @@ impl Thread {
         let globals = self.globals.get(mem);
         let instr = self.instr.get(mem);
 
-        // Establish a 256-register window into the stack from the stack base
+        // Establish a 256-register window into the stack from the stack base. Registers are only
+        // ever written with `store()`, without the write barrier: the whole stack is recorded as
+        // written to once per slice instead.
         stack.access_slice(mem, |full_stack| {
             let stack_base = self.stack_base.get() as usize;
             let window = &mut full_stack[stack_base..stack_base + 256];
@@ -310,7 +373,7 @@ impl Thread {
                 Opcode::Return { reg } => {
                     // write the return value to register 0
                     let result = window[reg as usize].get_ptr();
-                    window[RETURN_REG].set_to_ptr(result);
+                    window[RETURN_REG].store(result);
 
                     // remove this function's stack frame
                     frames.pop(mem)?;
@@ -322,37 +385,41 @@ impl Thread {
                         // otherwise restore the previous stack frame settings
                         let frame = frames.top(mem)?;
                         self.stack_base.set(frame.base);
-                        instr.switch_frame(frame.function.get(mem).code(mem), frame.ip.get());
+                        instr.switch_frame(
+                            mem,
+                            frame.function.get(mem).code(mem),
+                            frame.ip.get(),
+                        );
                     }
                 }
 
                 // Load a literal into a register from the function literals array
                 Opcode::LoadLiteral { dest, literal_id } => {
                     let literal_ptr = instr.get_literal(mem, literal_id)?;
-                    window[dest as usize].set_to_ptr(literal_ptr);
+                    window[dest as usize].store(literal_ptr);
                 }
 
                 // Evaluate whether the `test` register contains `nil` - if so, set the `dest`
-                // register to the symbol "true", otherwise set it to `nil`
+                // register to `true`, otherwise set it to `false`
                 Opcode::IsNil { dest, test } => {
                     let test_val = window[test as usize].get(mem);
 
                     match *test_val {
-                        Value::Nil => window[dest as usize].set(mem.lookup_sym("true")),
-                        _ => window[dest as usize].set_to_nil(),
+                        Value::Nil => window[dest as usize].store(mem.boolean(true).get_ptr()),
+                        _ => window[dest as usize].store(mem.boolean(false).get_ptr()),
                     }
                 }
 
                 // Evaluate whether the `test` register contains an atomic value - i.e. a
-                // non-container type. Set the `dest` register to "true" or `nil`.
+                // non-container type. Set the `dest` register to `true` or `false`.
                 Opcode::IsAtom { dest, test } => {
                     let test_val = window[test as usize].get(mem);
 
                     match *test_val {
-                        Value::Pair(_) => window[dest as usize].set_to_nil(),
-                        Value::Nil => window[dest as usize].set_to_nil(),
+                        Value::Pair(_) => window[dest as usize].store(mem.boolean(false).get_ptr()),
+                        Value::Nil => window[dest as usize].store(mem.boolean(false).get_ptr()),
                         // TODO what other types?
-                        _ => window[dest as usize].set(mem.lookup_sym("true")),
+                        _ => window[dest as usize].store(mem.boolean(true).get_ptr()),
                     }
                 }
 
@@ -361,7 +428,7 @@ impl Thread {
                     let reg_val = window[reg as usize].get(mem);
 
                     match *reg_val {
-                        Value::Pair(p) => window[dest as usize].set_to_ptr(p.first.get_ptr()),
+                        Value::Pair(p) => window[dest as usize].store(p.first.get_ptr()),
                         Value::Nil => window[dest as usize].set_to_nil(),
                         _ => return Err(err_eval("Parameter to FirstOfPair is not a list")),
                     }
@@ -372,7 +439,7 @@ impl Thread {
                     let reg_val = window[reg as usize].get(mem);
 
                     match *reg_val {
-                        Value::Pair(p) => window[dest as usize].set_to_ptr(p.second.get_ptr()),
+                        Value::Pair(p) => window[dest as usize].store(p.second.get_ptr()),
                         Value::Nil => window[dest as usize].set_to_nil(),
                         _ => return Err(err_eval("Parameter to SecondOfPair is not a list")),
                     }
@@ -384,24 +451,20 @@ impl Thread {
                     let reg2_val = window[reg2 as usize].get_ptr();
 
                     let new_pair = Pair::new();
-                    new_pair.first.set_to_ptr(reg1_val);
-                    new_pair.second.set_to_ptr(reg2_val);
+                    new_pair.first.set_to_ptr(mem, reg1_val);
+                    new_pair.second.set_to_ptr(mem, reg2_val);
 
-                    window[dest as usize].set(mem.alloc_tagged(new_pair)?);
+                    window[dest as usize].store(mem.alloc_tagged(new_pair)?.get_ptr());
                 }
 
-                // Identity comparison - if `test1` and `test2` are identical pointers, set `dest`
-                // to the symbol "true"
+                // Identity comparison - set `dest` to `true` if `test1` and `test2` are identical
+                // pointers, otherwise to `false`
                 Opcode::IsIdentical { dest, test1, test2 } => {
                     // compare raw pointers - identity comparison
                     let test1_val = window[test1 as usize].get_ptr();
                     let test2_val = window[test2 as usize].get_ptr();
 
-                    if test1_val == test2_val {
-                        window[dest as usize].set(mem.lookup_sym("true"));
-                    } else {
-                        window[dest as usize].set(mem.nil());
-                    }
+                    window[dest as usize].store(mem.boolean(test1_val == test2_val).get_ptr());
                 }
 
                 // Unconditional jump - advance the instruction pointer by `offset`
@@ -409,24 +472,16 @@ impl Thread {
                     instr.jump(offset);
                 }
 
-                // Jump if the `test` register contains the symbol "true"
+                // Jump if the `test` register is truthy: anything but `false` and `nil`
                 Opcode::JumpIfTrue { test, offset } => {
-                    let test_val = window[test as usize].get(mem);
-
-                    let true_sym = mem.lookup_sym("true"); // TODO preload keyword syms
-
-                    if test_val == true_sym {
+                    if window[test as usize].get_ptr().is_truthy() {
                         instr.jump(offset)
                     }
                 }
 
-                // Jump if the `test` register does not contain the symbol "true"
+                // Jump if the `test` register is `false` or `nil`
                 Opcode::JumpIfNotTrue { test, offset } => {
-                    let test_val = window[test as usize].get(mem);
-
-                    let true_sym = mem.lookup_sym("true");
-
-                    if test_val != true_sym {
+                    if !window[test as usize].get_ptr().is_truthy() {
                         instr.jump(offset)
                     }
                 }
@@ -439,7 +494,7 @@ impl Thread {
                 // Set the register `dest` to the inline integer literal
                 Opcode::LoadInteger { dest, integer } => {
                     let tagged_ptr = TaggedPtr::literal_integer(integer);
-                    window[dest as usize].set_to_ptr(tagged_ptr);
+                    window[dest as usize].store(tagged_ptr);
                 }
 
                 // Lookup a global binding and put it in the register `dest`
@@ -450,7 +505,7 @@ impl Thread {
                         let lookup_result = globals.lookup(mem, name_val);
 
                         match lookup_result {
-                            Ok(binding) => window[dest as usize].set(binding),
+                            Ok(binding) => window[dest as usize].store(binding.get_ptr()),
                             Err(_) => {
                                 return Err(err_eval(&format!(
                                     "Symbol {} is not bound to a value",
@@ -511,7 +566,7 @@ impl Thread {
                         // Update the instruction stream to point to the new function
                         let code = function.code(mem);
                         self.stack_base.set(new_stack_base);
-                        instr.switch_frame(code, 0);
+                        instr.switch_frame(mem, code, 0);
 
                         // Ensure the stack has 256 registers allocated
                         // TODO reset to nil to avoid accidental leakage of previous call values
@@ -540,7 +595,7 @@ impl Thread {
                                     &window[args_start..args_end],
                                 )?;
 
-                                window[dest as usize].set(partial.as_tagged(mem));
+                                window[dest as usize].store(partial.as_tagged(mem).get_ptr());
 
                                 return Ok(EvalStatus::Pending);
                             } else if arg_count > arity {
@@ -561,8 +616,7 @@ impl Thread {
 
                             if arg_count == 0 && arity > 0 {
                                 // Partial is unchanged, no args added, copy directly to dest
-                                window[dest as usize]
-                                    .set_to_ptr(window[function as usize].get_ptr());
+                                window[dest as usize].store(window[function as usize].get_ptr());
                                 return Ok(EvalStatus::Pending);
                             } else if arg_count < arity {
                                 // Too few args, bake a new Partial from the existing one, adding the new
@@ -576,7 +630,7 @@ impl Thread {
                                     &window[args_start..args_end],
                                 )?;
 
-                                window[dest as usize].set(new_partial.as_tagged(mem));
+                                window[dest as usize].store(new_partial.as_tagged(mem).get_ptr());
 
                                 return Ok(EvalStatus::Pending);
                             } else if arg_count > arity {
@@ -590,7 +644,7 @@ impl Thread {
                             }
 
                             // Copy closure env pointer
-                            window[dest as usize + ENV_REG] = partial.closure_env();
+                            window[dest as usize + ENV_REG].store(partial.closure_env().get_ptr());
 
                             // Shunt _call_ args back into the window to make space for the
                             // partially applied args
@@ -598,7 +652,7 @@ impl Thread {
                             let from_reg = dest as usize + FIRST_ARG_REG;
                             let to_reg = from_reg + push_dist as usize;
                             for index in (0..arg_count as usize).rev() {
-                                window[to_reg + index] = window[from_reg + index].clone();
+                                window[to_reg + index].store(window[from_reg + index].get_ptr());
                             }
 
                             // copy args from Partial to the register window
@@ -606,13 +660,33 @@ impl Thread {
                             let start_reg = dest as usize + FIRST_ARG_REG;
                             args.access_slice(mem, |items| {
                                 for (index, item) in items.iter().enumerate() {
-                                    window[start_reg + index] = item.clone();
+                                    window[start_reg + index].store(item.get_ptr());
                                 }
                             });
 
                             new_call_frame(partial.function(mem))?;
                         }
 
+                        Value::Builtin(builtin) => {
+                            let arity = builtin.arity();
+
+                            if arg_count != arity {
+                                return Err(err_eval(&format!(
+                                    "Builtin {} expected {} arguments, got {}",
+                                    builtin.name(mem),
+                                    arity,
+                                    arg_count
+                                )));
+                            }
+
+                            // Builtins run to completion without a call frame
+                            let args_start = dest as usize + FIRST_ARG_REG;
+                            let args_end = args_start + arg_count as usize;
+
+                            let result = builtin.call(mem, &window[args_start..args_end])?;
+                            window[dest as usize].store(result.get_ptr());
+                        }
+
                         _ => return Err(err_eval("Type is not callable")),
                     }
                 }
@@ -660,7 +734,7 @@ impl Thread {
                         // Instantiate a Partial function application from the closure environment
                         // and set the destination register
                         let partial = Partial::alloc(mem, f, Some(env), &[])?;
-                        window[dest as usize].set(partial.as_tagged(mem));
+                        window[dest as usize].store(partial.as_tagged(mem).get_ptr());
                     } else {
                         return Err(err_eval("Cannot make a closure from a non-Function type"));
                     }
@@ -669,27 +743,43 @@ impl Thread {
 
                 // Simple copy of one register to another
                 Opcode::CopyRegister { dest, src } => {
-                    window[dest as usize] = window[src as usize].clone();
+                    window[dest as usize].store(window[src as usize].get_ptr());
                 }
 
-                // TODO
-                Opcode::Add { dest, reg1, reg2 } => unimplemented!(),
+                // Arithmetic. Integer results outside the tagged number range are promoted to
+                // NumberObjects and results that fit are always tagged numbers. Any Float
+                // operand makes the result a Float.
+                Opcode::Add { dest, reg1, reg2 } => {
+                    let a = window[reg1 as usize].get(mem);
+                    let b = window[reg2 as usize].get(mem);
+                    window[dest as usize].store(Numbers::add(mem, a, b)?.get_ptr());
+                }
 
-                // TODO
-                Opcode::Subtract { dest, left, right } => unimplemented!(),
+                Opcode::Subtract { dest, left, right } => {
+                    let a = window[left as usize].get(mem);
+                    let b = window[right as usize].get(mem);
+                    window[dest as usize].store(Numbers::subtract(mem, a, b)?.get_ptr());
+                }
 
-                // TODO
-                Opcode::Multiply { dest, reg1, reg2 } => unimplemented!(),
+                Opcode::Multiply { dest, reg1, reg2 } => {
+                    let a = window[reg1 as usize].get(mem);
+                    let b = window[reg2 as usize].get(mem);
+                    window[dest as usize].store(Numbers::multiply(mem, a, b)?.get_ptr());
+                }
 
-                // TODO
-                Opcode::DivideInteger { dest, num, denom } => unimplemented!(),
+                // Integer division truncates towards zero
+                Opcode::DivideInteger { dest, num, denom } => {
+                    let a = window[num as usize].get(mem);
+                    let b = window[denom as usize].get(mem);
+                    window[dest as usize].store(Numbers::divide_integer(mem, a, b)?.get_ptr());
+                }
 
                 // Follow the indirection of an Upvalue to retrieve the value, copy the value to a
                 // local register
                 Opcode::GetUpvalue { dest, src } => {
                     let closure_env = window[ENV_REG].get(mem);
                     let upvalue = env_upvalue_lookup(mem, closure_env, src)?;
-                    window[dest as usize].set_to_ptr(upvalue.get(mem, stack)?);
+                    window[dest as usize].store(upvalue.get(mem, stack)?);
                 }
 
                 // Follow the indirection of an Upvalue to set the value from a local register
@@ -720,18 +810,12 @@ impl Thread {
         })
     }
 
-    /// Given ByteCode, execute up to max_instr more instructions
+    /// Execute up to max_instr more instructions from wherever the instruction stream stopped
     fn vm_eval_stream<'guard>(
         &self,
         mem: &'guard MutatorView,
-        code: ScopedPtr<'guard, ByteCode>,
         max_instr: ArraySize,
     ) -> Result<EvalStatus<'guard>, RuntimeError> {
-        let instr = self.instr.get(mem);
-        // TODO this is broken logic, this function shouldn't switch back to this code object every
-        // time it is called
-        instr.switch_frame(code, 0);
-
         for _ in 0..max_instr {
             match self.eval_next_instr(mem) {
                 // Evaluation paused or completed without error
@@ -770,10 +854,28 @@ impl Thread {
 
     /// Evaluate a Function completely, returning the result. The Function passed in should expect
     /// no arguments.
+    ///
+    /// The Thread is registered as a root for the duration so that the heap can collect between
+    /// instruction slices and when an allocation fails. In incremental mode a step of marking
+    /// runs between slices. While profiling, allocations are attributed to the Thread's frames.
     pub fn quick_vm_eval<'guard>(
         &self,
         mem: &'guard MutatorView,
         function: ScopedPtr<'guard, Function>,
+    ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
+        mem.add_root(self);
+        mem.set_call_stack(Some(self));
+        let result = self.quick_vm_eval_rooted(mem, function);
+        mem.set_call_stack(None);
+        mem.remove_root(self);
+
+        result
+    }
+
+    fn quick_vm_eval_rooted<'guard>(
+        &self,
+        mem: &'guard MutatorView,
+        function: ScopedPtr<'guard, Function>,
     ) -> Result<TaggedScopedPtr<'guard>, RuntimeError> {
         let mut status = EvalStatus::Pending;
 
@@ -781,15 +883,55 @@ impl Thread {
         frames.push(mem, CallFrame::new_main(function))?;
 
         let code = function.code(mem);
+        self.instr.get(mem).switch_frame(mem, code, 0);
 
         while status == EvalStatus::Pending {
-            status = self.vm_eval_stream(mem, code, 1024)?;
+            status = self.vm_eval_stream(mem, 1024)?;
             match status {
                 EvalStatus::Return(value) => return Ok(value),
-                _ => (),
+                _ => self.end_slice(mem),
             }
         }
 
         Err(err_eval("Unexpected end of evaluation"))
     }
+
+    /// Called between instruction slices, where everything live is reachable from the Thread.
+    /// Registers are written without the write barrier, so the stack is recorded as written to
+    /// once per slice, before a collection can run.
+    fn end_slice(&self, mem: &MutatorView) {
+        mem.write_barrier(&*self.stack.get(mem));
+        mem.safepoint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::evalrus::Heap::{Heap, HeapStorage};
+    use crate::evalrus::HeapConfig::HeapConfig;
+    use crate::internals::Alloc::AllocRaw;
+    use super::*;
+
+    #[test]
+    fn young_object_in_old_register_window_survives_minor_collection() {
+        let heap = Heap::new(HeapConfig::default());
+        let mem = MutatorView { heap: &heap };
+
+        // promote the Thread and its register stack
+        let thread = Thread::alloc(&mem).unwrap();
+        heap.safepoint();
+        mem.collect_minor(&*thread);
+
+        // the stack is old, so the young object can only be found through the write barrier
+        let young = mem.alloc_tagged(Pair::new()).unwrap();
+        thread.stack.get(&mem).access_slice(&mem, |registers| {
+            registers[FIRST_ARG_REG].store(young.get_ptr())
+        });
+        thread.end_slice(&mem);
+        mem.collect_minor(&*thread);
+
+        let object = young.get_ptr().heap_object().unwrap();
+        assert!(unsafe { HeapStorage::get_header(object).as_ref() }.is_marked());
+        assert!(heap.verify(Some(&*thread)).is_ok());
+    }
 }
@@ -1,6 +1,7 @@
 use std::intrinsics::size_of;
 use std::ptr::NonNull;
 use crate::frontend::Array::ArraySize;
+use crate::internals::Block::BlockError;
 use crate::internals::constants;
 
 
@@ -18,6 +19,8 @@ pub enum Mark {
     Allocated,
     Unmarked,
     Marked,
+    /// The object was evacuated; its new address is held in the old object's first word
+    Forwarded,
 }
 
 #[repr(u8)]
@@ -61,6 +64,9 @@ pub trait AllocHeader: Sized {
     /// Set the Mark value to "marked"
     fn mark(&mut self);
 
+    /// Set the Mark value to "unmarked", ready for the next collection
+    fn unmark(&mut self);
+
     /// Get the current Mark value
     fn is_marked(&self) -> bool;
 
@@ -123,3 +129,13 @@ pub enum AllocError {
     /// Out of memory - allocating the space failed
     OOM,
 }
+
+/// Convert from BlockError
+impl From<BlockError> for AllocError {
+    fn from(other: BlockError) -> AllocError {
+        match other {
+            BlockError::OOM => AllocError::OOM,
+            BlockError::BadRequest => AllocError::BadRequest,
+        }
+    }
+}
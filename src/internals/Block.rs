@@ -1,5 +1,6 @@
 use std::ptr::NonNull;
-use crate::internals::internal;
+use std::rc::Rc;
+use crate::internals::BlockSource::{BlockSource, SystemSource};
 
 pub type BlockPtr = NonNull<u8>;
 pub type BlockSize = usize;
@@ -8,19 +9,42 @@ pub type BlockSize = usize;
 pub struct Block {
     ptr: BlockPtr,
     size: BlockSize,
+    /// Where the block came from and is returned to when dropped
+    source: Rc<dyn BlockSource>,
 }
 
 impl Block {
+    /// Allocate a block from the global Rust allocator
     pub fn new(size: BlockSize) -> Result<Block, BlockError> {
+        Block::with_source(size, Rc::new(SystemSource))
+    }
+
+    /// Allocate a block from the given source
+    pub fn with_source(size: BlockSize, source: Rc<dyn BlockSource>) -> Result<Block, BlockError> {
         if !size.is_power_of_two() {
             return Err(BlockError::BadRequest);
         }
 
         Ok(Block {
-            ptr: internal::alloc_block(size)?,
+            ptr: source.alloc_block(size)?,
             size,
+            source,
         })
     }
+
+    pub fn as_ptr(&self) -> *const u8 {
+        self.ptr.as_ptr()
+    }
+
+    pub fn size(&self) -> BlockSize {
+        self.size
+    }
+}
+
+impl Drop for Block {
+    fn drop(&mut self) {
+        self.source.dealloc_block(self.ptr, self.size);
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -1,43 +1,346 @@
 use std::cell::UnsafeCell;
+use std::collections::HashSet;
 use std::marker::PhantomData;
 use std::mem::replace;
+use std::rc::Rc;
 use crate::internals::Alloc::AllocError;
+use crate::internals::BlockSource::{BlockSource, SystemSource};
+use crate::internals::BlockStats::BlockStats;
 use crate::internals::BumpBlock::BumpBlock;
+use crate::internals::constants;
+
+/// What to do with blocks found to be entirely free after a collection
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ReleasePolicy {
+    /// Keep every free block for reuse; the heap never shrinks
+    RetainAll,
+    /// Keep at most this many free blocks, returning the rest to the OS
+    RetainAtMost(usize),
+    /// Return every free block to the OS
+    ReleaseAll,
+}
 
 pub struct BlockList {
     pub head: Option<BumpBlock>,
     pub overflow: Option<BumpBlock>,
     pub rest: Vec<BumpBlock>,
+    /// Blocks that still contain live objects but have at least one free hole
+    pub recycle: Vec<BumpBlock>,
+    /// Blocks that contain no live objects at all
+    pub free: Vec<BumpBlock>,
+    /// Sparsely occupied blocks whose objects are being moved out during a full collection
+    pub evacuating: Vec<BumpBlock>,
+    /// The block evacuated objects are copied into
+    pub evacuation_target: Option<BumpBlock>,
+    /// How many free blocks to keep after a collection
+    pub release_policy: ReleasePolicy,
+    /// The most blocks the list may hold, or None for no limit
+    pub block_limit: Option<usize>,
+    /// Fill reclaimed lines with `POISON_BYTE` after each sweep
+    pub poison: bool,
+    /// Where fresh blocks are allocated from
+    pub source: Rc<dyn BlockSource>,
+    /// Count of blocks taken for overflow allocation since the list was created
+    overflow_count: usize,
+    /// Count of blocks taken from the block source since the list was created
+    acquired_count: usize,
+    /// Count of blocks returned to the block source since the list was created
+    released_count: usize,
+    /// Base addresses of every block in the list
+    addresses: HashSet<usize>,
+    /// Told about blocks as they are taken from or returned to the block source
+    pub listener: Option<TurnoverListener>,
+}
+
+/// A change in the blocks held by a list, reported as it happens
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BlockTurnover {
+    Acquired { count: usize, blocks_in_use: usize },
+    Released { count: usize, blocks_in_use: usize },
+}
+
+pub type TurnoverListener = Box<dyn Fn(BlockTurnover)>;
+
+fn notify(listener: &Option<TurnoverListener>, turnover: BlockTurnover) {
+    if let Some(listener) = listener {
+        listener(turnover);
+    }
 }
+
 impl BlockList {
-    pub(crate) fn overflow_alloc(&mut self, alloc_size: usize) -> Result<*const u8, AllocError> {
-        match self.overflow {
-            Some(ref mut overflow) => {
-                // This is a medium object that might fit in the current block...
-                match overflow.inner_alloc(alloc_size) {
-                    // the block has a suitable hole
-                    Some(space) => Ok(space),
-                    None => {
-                        let previous = replace(overflow, BumpBlock::new()?);
-
-                        self.rest.push(previous);
-
-                        overflow.inner_alloc(alloc_size).expect("Unexpected error!")
-                    }
-                }
+    pub fn new() -> BlockList {
+        BlockList {
+            head: None,
+            overflow: None,
+            rest: Vec::new(),
+            recycle: Vec::new(),
+            free: Vec::new(),
+            evacuating: Vec::new(),
+            evacuation_target: None,
+            release_policy: ReleasePolicy::RetainAtMost(constants::DEFAULT_FREE_BLOCKS_RETAINED),
+            block_limit: None,
+            poison: false,
+            source: Rc::new(SystemSource),
+            overflow_count: 0,
+            acquired_count: 0,
+            released_count: 0,
+            addresses: HashSet::new(),
+            listener: None,
+        }
+    }
+
+    /// Allocate a fresh block, recording its address. Fails with OOM if the block limit has
+    /// been reached.
+    fn new_block(&mut self) -> Result<BumpBlock, AllocError> {
+        if let Some(limit) = self.block_limit {
+            if self.addresses.len() >= limit {
+                return Err(AllocError::OOM);
+            }
+        }
+
+        let block = BumpBlock::new(&self.source)?;
+        self.addresses.insert(block.base());
+        self.acquired_count += 1;
+        notify(
+            &self.listener,
+            BlockTurnover::Acquired {
+                count: 1,
+                blocks_in_use: self.addresses.len(),
             },
-            None => {
-                let mut overflow = BumpBlock::new()?;
+        );
+        Ok(block)
+    }
+
+    /// Count of blocks in the list
+    pub fn len(&self) -> usize {
+        self.addresses.len()
+    }
 
-                // object size < block size means we can't fail this expect
-                let space = overflow
-                    .inner_alloc(alloc_size)
-                    .expect("We expected this object to fit!");
+    /// Return true if the address lies within one of the blocks in the list
+    pub fn contains(&self, addr: *const u8) -> bool {
+        self.addresses
+            .contains(&(addr as usize & constants::BLOCK_PTR_MASK))
+    }
 
-                self.overflow = Some(overflow);
+    /// Take the next block to bump-allocate small objects into: recyclable blocks first, so that
+    /// holes get filled, then free blocks, and only then a fresh block.
+    pub(crate) fn next_block(&mut self) -> Result<BumpBlock, AllocError> {
+        match self.recycle.pop() {
+            Some(block) => Ok(block),
+            None => self.next_free_block(),
+        }
+    }
 
-                space
+    /// Take an entirely free block, or a fresh one
+    pub(crate) fn next_free_block(&mut self) -> Result<BumpBlock, AllocError> {
+        match self.free.pop() {
+            Some(block) => Ok(block),
+            None => self.new_block(),
+        }
+    }
+
+    pub(crate) fn overflow_alloc(&mut self, alloc_size: usize) -> Result<*const u8, AllocError> {
+        // This is a medium object that might fit in a hole in the current overflow block...
+        if let Some(ref mut overflow) = self.overflow {
+            if let Some(space) = overflow.inner_alloc(alloc_size) {
+                return Ok(space);
             }
-        }    }
-}
+        }
+
+        // ...otherwise it goes in a new overflow block
+        let mut next = self.next_free_block()?;
+
+        // object size < block size means we can't fail this expect
+        let space = next
+            .inner_alloc(alloc_size)
+            .expect("We expected this object to fit!");
+
+        if let Some(previous) = self.overflow.replace(next) {
+            self.rest.push(previous);
+        }
+        self.overflow_count += 1;
+
+        Ok(space)
+    }
 
+    /// Move the most sparsely occupied recyclable blocks, judged by the line marks of the last
+    /// collection, to the evacuation list. Must be called before the line marks are reset. Only
+    /// as many blocks are selected as their live lines can be copied into free blocks plus a few
+    /// fresh ones.
+    pub(crate) fn select_evacuation_candidates(&mut self) {
+        let mut candidates: Vec<(usize, usize)> = self
+            .recycle
+            .iter()
+            .enumerate()
+            .map(|(index, block)| (block.marked_lines(), index))
+            .filter(|(live, _)| *live < constants::EVACUATION_MAX_LIVE_LINES)
+            .collect();
+        candidates.sort();
+
+        let mut headroom = (self.free.len() + constants::EVACUATION_HEADROOM_BLOCKS)
+            * constants::USABLE_LINE_COUNT;
+        let mut selected = Vec::new();
+
+        for (live, index) in candidates {
+            if live > headroom {
+                break;
+            }
+            headroom -= live;
+            selected.push(index);
+        }
+
+        // remove from the back so that the remaining indexes stay valid
+        selected.sort_unstable_by(|a, b| b.cmp(a));
+        for index in selected {
+            let block = self.recycle.swap_remove(index);
+            block.set_evacuating(true);
+            self.evacuating.push(block);
+        }
+    }
+
+    /// Allocate space for an object being evacuated. Evacuated objects are only copied into
+    /// entirely free blocks: the line marks of every other block are reset during the mark
+    /// phase so their holes can't be trusted.
+    pub(crate) fn evacuation_alloc(&mut self, alloc_size: usize) -> Result<*const u8, AllocError> {
+        if let Some(ref mut target) = self.evacuation_target {
+            if let Some(space) = target.inner_alloc(alloc_size) {
+                return Ok(space);
+            }
+        }
+
+        let mut target = self.next_free_block()?;
+        let space = target
+            .inner_alloc(alloc_size)
+            .expect("We expected this object to fit!");
+
+        if let Some(previous) = replace(&mut self.evacuation_target, Some(target)) {
+            self.rest.push(previous);
+        }
+
+        Ok(space)
+    }
+
+    /// Iterate over every block in the list
+    fn iter(&self) -> impl Iterator<Item = &BumpBlock> {
+        self.head.iter()
+            .chain(self.overflow.iter())
+            .chain(self.rest.iter())
+            .chain(self.recycle.iter())
+            .chain(self.free.iter())
+            .chain(self.evacuating.iter())
+            .chain(self.evacuation_target.iter())
+    }
+
+    /// Count blocks and lines by state
+    pub fn stats(&self) -> BlockStats {
+        let lines_marked: usize = self.iter().map(|block| block.marked_lines()).sum();
+        let lines_total = self.iter().count() * constants::USABLE_LINE_COUNT;
+
+        BlockStats {
+            blocks_in_use: self.addresses.len(),
+            free_blocks: self.free.len(),
+            recyclable_blocks: self.recycle.len(),
+            overflow_blocks: self.overflow_count,
+            blocks_acquired: self.acquired_count,
+            blocks_released: self.released_count,
+            lines_marked,
+            lines_free: lines_total - lines_marked,
+            ..Default::default()
+        }
+    }
+
+    /// Check every block for consistency, returning the base address of each block with a
+    /// description of the problem found in it. Unless `check_marks` is set, marked lines in the
+    /// current hole of a block are allowed.
+    pub fn verify(&self, check_marks: bool) -> Vec<(usize, String)> {
+        let mut problems = Vec::new();
+
+        for block in self.iter() {
+            if !self.addresses.contains(&block.base()) {
+                problems.push((block.base(), String::from("block address is not recorded")));
+            }
+
+            for problem in block.verify(check_marks) {
+                problems.push((block.base(), problem));
+            }
+        }
+
+        if self.iter().count() != self.addresses.len() {
+            problems.push((0, String::from("recorded block addresses do not match the blocks held")));
+        }
+
+        problems
+    }
+
+    /// Clear the line marks of every block ahead of a mark phase
+    pub(crate) fn reset_marks(&mut self) {
+        self.head.iter_mut()
+            .chain(self.overflow.iter_mut())
+            .chain(self.rest.iter_mut())
+            .chain(self.recycle.iter_mut())
+            .chain(self.free.iter_mut())
+            .chain(self.evacuating.iter_mut())
+            .for_each(|block| block.reset_marks());
+    }
+
+    /// After a mark phase, sort every block into free, recyclable or full according to its line
+    /// marks. The head and overflow blocks are retired too so that the next allocation starts in
+    /// the best available hole.
+    pub(crate) fn sweep(&mut self) {
+        let mut blocks = replace(&mut self.rest, Vec::new());
+        blocks.extend(self.head.take());
+        blocks.extend(self.overflow.take());
+        blocks.append(&mut self.recycle);
+        blocks.append(&mut self.free);
+        for block in self.evacuating.drain(..) {
+            block.set_evacuating(false);
+            blocks.push(block);
+        }
+        blocks.extend(self.evacuation_target.take());
+
+        for mut block in blocks {
+            if self.poison {
+                block.poison_free_lines();
+            }
+
+            if block.is_free() {
+                block.reset_hole();
+                self.free.push(block);
+            } else if block.reset_hole() {
+                self.recycle.push(block);
+            } else {
+                self.rest.push(block);
+            }
+        }
+
+        self.release_free_blocks();
+    }
+
+    /// Drop free blocks in excess of the release policy, deallocating their memory
+    fn release_free_blocks(&mut self) {
+        let retain = match self.release_policy {
+            ReleasePolicy::RetainAll => return,
+            ReleasePolicy::RetainAtMost(count) => count,
+            ReleasePolicy::ReleaseAll => 0,
+        };
+
+        let mut count = 0;
+        while self.free.len() > retain {
+            if let Some(block) = self.free.pop() {
+                self.addresses.remove(&block.base());
+                self.released_count += 1;
+                count += 1;
+            }
+        }
+
+        if count > 0 {
+            notify(
+                &self.listener,
+                BlockTurnover::Released {
+                    count,
+                    blocks_in_use: self.addresses.len(),
+                },
+            );
+        }
+    }
+}
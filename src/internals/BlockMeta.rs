@@ -1,10 +1,107 @@
+use std::sync::atomic::{AtomicU8, Ordering};
 use crate::internals::constants;
 
+/// The mark byte of the line before the block mark also belongs to the mark bytes' own lines,
+/// so it is free to flag a block selected for evacuation
+const EVACUATING_INDEX: usize = constants::LINE_COUNT - 2;
+
 pub struct BlockMeta {
     lines: *mut u8,
 }
 
 impl BlockMeta {
+    /// Create the metadata for a newly allocated block, with every line unmarked
+    pub fn new(block_ptr: *const u8) -> BlockMeta {
+        let mut meta = BlockMeta::at(block_ptr);
+        meta.reset();
+        meta.set_evacuating(false);
+        meta
+    }
+
+    /// The line mark bytes live at the end of the block itself
+    fn at(block_ptr: *const u8) -> BlockMeta {
+        BlockMeta {
+            lines: unsafe { block_ptr.add(constants::LINE_MARK_START) as *mut u8 },
+        }
+    }
+
+    /// Mark every line spanned by an object of `size` bytes at `object`, and the block that
+    /// contains it. The block is found by masking the object address: blocks are aligned to
+    /// their size.
+    pub fn mark_object_lines(object: *const u8, size: usize) {
+        let addr = object as usize;
+        let block = addr & constants::BLOCK_PTR_MASK;
+        let meta = BlockMeta::at(block as *const u8);
+
+        let first_line = (addr - block) / constants::LINE_SIZE;
+        let last_line = (addr - block + size - 1) / constants::LINE_SIZE;
+
+        for index in first_line..=last_line {
+            meta.mark_line(index);
+        }
+        meta.mark_block();
+    }
+
+    /// Mark bytes are written atomically so that mark threads can mark lines in the same block
+    /// concurrently
+    fn mark_byte(&self, index: usize) -> &AtomicU8 {
+        unsafe { &*(self.lines.add(index) as *const AtomicU8) }
+    }
+
+    pub fn mark_line(&self, index: usize) {
+        self.mark_byte(index).store(1, Ordering::Relaxed);
+    }
+
+    /// The last line of the block holds the mark bytes themselves and is never allocated into,
+    /// so its mark byte is used to mark the block as a whole.
+    pub fn mark_block(&self) {
+        self.mark_byte(constants::LINE_COUNT - 1).store(1, Ordering::Relaxed);
+    }
+
+    /// Flag or unflag the block as selected for evacuation
+    pub fn set_evacuating(&self, evacuating: bool) {
+        self.mark_byte(EVACUATING_INDEX)
+            .store(evacuating as u8, Ordering::Relaxed);
+    }
+
+    /// Return true if the block containing the address is selected for evacuation. The address
+    /// must lie within a block, not a large object.
+    pub fn is_evacuating(object: *const u8) -> bool {
+        let block = object as usize & constants::BLOCK_PTR_MASK;
+        let meta = BlockMeta::at(block as *const u8);
+        meta.mark_byte(EVACUATING_INDEX).load(Ordering::Relaxed) != 0
+    }
+
+    /// Return true if no object in the block was marked in the last collection
+    pub fn is_block_free(&self) -> bool {
+        unsafe { *self.lines.add(constants::LINE_COUNT - 1) == 0 }
+    }
+
+    /// Count the lines marked in the last collection, not including the block mark
+    pub fn marked_lines(&self) -> usize {
+        (0..constants::USABLE_LINE_COUNT)
+            .filter(|index| self.is_line_marked(*index))
+            .count()
+    }
+
+    pub fn is_line_marked(&self, index: usize) -> bool {
+        self.line_mark(index) != 0
+    }
+
+    /// The raw mark byte of a line, or of the block for index `LINE_COUNT - 1`
+    pub fn line_mark(&self, index: usize) -> u8 {
+        unsafe { *self.lines.add(index) }
+    }
+
+    /// Clear all line and block marks, ahead of a mark phase. The evacuation flag is left as it
+    /// is: blocks are selected before the marks are cleared.
+    pub fn reset(&mut self) {
+        for index in 0..constants::LINE_COUNT {
+            if index != EVACUATING_INDEX {
+                unsafe { *self.lines.add(index) = 0 };
+            }
+        }
+    }
 
     /// When it comes to finding allocatable holes, we bump-allocate downward.
     pub fn find_next_available_hole(
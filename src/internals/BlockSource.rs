@@ -0,0 +1,274 @@
+use std::alloc::{alloc, dealloc, Layout};
+use std::cell::{Cell, RefCell};
+use std::ptr::NonNull;
+use std::rc::Rc;
+use crate::internals::Block::{BlockError, BlockPtr, BlockSize};
+use crate::internals::constants;
+use crate::internals::internal;
+
+/// Where the memory for blocks comes from. Blocks must be aligned to their size, which is always
+/// a power of two.
+pub trait BlockSource {
+    /// Provide a block of `size` bytes aligned to `size`
+    fn alloc_block(&self, size: BlockSize) -> Result<BlockPtr, BlockError>;
+
+    /// Take back a block given out by `alloc_block`. The size must be the same as was requested.
+    fn dealloc_block(&self, ptr: BlockPtr, size: BlockSize);
+
+    /// Provide memory for a large object of `size` bytes aligned to `align`, a power of two.
+    /// Defaults to the global Rust allocator.
+    fn alloc_large(&self, size: usize, align: usize) -> Result<BlockPtr, BlockError> {
+        let layout = Layout::from_size_align(size, align).map_err(|_| BlockError::BadRequest)?;
+        NonNull::new(unsafe { alloc(layout) }).ok_or(BlockError::OOM)
+    }
+
+    /// Take back memory given out by `alloc_large`, with the same size and alignment
+    fn dealloc_large(&self, ptr: BlockPtr, size: usize, align: usize) {
+        if let Ok(layout) = Layout::from_size_align(size, align) {
+            unsafe { dealloc(ptr.as_ptr(), layout) }
+        }
+    }
+}
+
+/// Which block source a heap is created with, given in `HeapConfig`
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BlockSourceKind {
+    /// The global Rust allocator
+    System,
+    /// Anonymous memory mappings, falling back to the global allocator where mmap is unavailable
+    Mmap,
+    /// A single region of this many bytes reserved up front; the heap can never grow past it
+    Fixed(usize),
+}
+
+impl BlockSourceKind {
+    /// Create a block source of this kind
+    pub fn create(&self) -> Result<Rc<dyn BlockSource>, BlockError> {
+        match *self {
+            BlockSourceKind::System => Ok(Rc::new(SystemSource)),
+            #[cfg(unix)]
+            BlockSourceKind::Mmap => Ok(Rc::new(MmapSource::new())),
+            #[cfg(not(unix))]
+            BlockSourceKind::Mmap => Ok(Rc::new(SystemSource)),
+            BlockSourceKind::Fixed(bytes) => Ok(Rc::new(FixedRegion::new(bytes)?)),
+        }
+    }
+}
+
+/// Blocks allocated from and freed straight back to the global Rust allocator
+pub struct SystemSource;
+
+impl BlockSource for SystemSource {
+    fn alloc_block(&self, size: BlockSize) -> Result<BlockPtr, BlockError> {
+        internal::alloc_block(size)
+    }
+
+    fn dealloc_block(&self, ptr: BlockPtr, size: BlockSize) {
+        internal::dealloc_block(ptr, size)
+    }
+}
+
+/// Blocks mapped directly from the OS. Released blocks stay mapped for reuse but their pages are
+/// handed back with `madvise(MADV_DONTNEED)`, so they no longer count against resident memory.
+#[cfg(unix)]
+pub struct MmapSource {
+    /// Released blocks and their sizes, mapped but without backing pages
+    released: RefCell<Vec<(BlockPtr, BlockSize)>>,
+}
+
+#[cfg(unix)]
+impl MmapSource {
+    pub fn new() -> MmapSource {
+        MmapSource {
+            released: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Map `size` bytes aligned to `size` by over-mapping and unmapping the slop either side
+    fn map_aligned(size: BlockSize) -> Result<BlockPtr, BlockError> {
+        let span = size.checked_mul(2).ok_or(BlockError::BadRequest)?;
+
+        unsafe {
+            let base = libc::mmap(
+                std::ptr::null_mut(),
+                span,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            if base == libc::MAP_FAILED {
+                return Err(BlockError::OOM);
+            }
+
+            let base = base as usize;
+            let aligned = (base + size - 1) & !(size - 1);
+
+            let head = aligned - base;
+            if head > 0 {
+                libc::munmap(base as *mut libc::c_void, head);
+            }
+            let tail = span - head - size;
+            if tail > 0 {
+                libc::munmap((aligned + size) as *mut libc::c_void, tail);
+            }
+
+            Ok(NonNull::new_unchecked(aligned as *mut u8))
+        }
+    }
+}
+
+#[cfg(unix)]
+impl BlockSource for MmapSource {
+    fn alloc_block(&self, size: BlockSize) -> Result<BlockPtr, BlockError> {
+        let mut released = self.released.borrow_mut();
+
+        // DONTNEED pages read back as zero on next touch, as a fresh mapping would
+        if let Some(index) = released.iter().position(|&(_, block_size)| block_size == size) {
+            return Ok(released.swap_remove(index).0);
+        }
+
+        MmapSource::map_aligned(size)
+    }
+
+    fn dealloc_block(&self, ptr: BlockPtr, size: BlockSize) {
+        unsafe {
+            libc::madvise(ptr.as_ptr() as *mut libc::c_void, size, libc::MADV_DONTNEED);
+        }
+        self.released.borrow_mut().push((ptr, size));
+    }
+}
+
+#[cfg(unix)]
+impl Drop for MmapSource {
+    fn drop(&mut self) {
+        for (ptr, size) in self.released.borrow_mut().drain(..) {
+            unsafe {
+                libc::munmap(ptr.as_ptr() as *mut libc::c_void, size);
+            }
+        }
+    }
+}
+
+/// Blocks and large objects carved out of one region of memory reserved up front. Allocation
+/// fails with OOM once the region is used up, which puts a hard ceiling on the heap for
+/// embedding in memory-constrained processes.
+pub struct FixedRegion {
+    base: usize,
+    len: usize,
+    /// Offset of the first byte never yet handed out
+    next: Cell<usize>,
+    /// Offsets and sizes of blocks and large objects given back
+    released: RefCell<Vec<(usize, BlockSize)>>,
+    /// The layout the region was allocated with, if the region owns its memory
+    owned: Option<Layout>,
+}
+
+impl FixedRegion {
+    /// Reserve a region of `bytes` bytes from the global allocator, aligned so that no space is
+    /// lost aligning the first block
+    pub fn new(bytes: usize) -> Result<FixedRegion, BlockError> {
+        if bytes == 0 {
+            return Err(BlockError::BadRequest);
+        }
+
+        let layout = Layout::from_size_align(bytes, constants::BLOCK_SIZE)
+            .map_err(|_| BlockError::BadRequest)?;
+
+        let base = unsafe { alloc(layout) };
+        if base.is_null() {
+            return Err(BlockError::OOM);
+        }
+
+        Ok(FixedRegion {
+            base: base as usize,
+            len: bytes,
+            next: Cell::new(0),
+            released: RefCell::new(Vec::new()),
+            owned: Some(layout),
+        })
+    }
+
+    /// Carve blocks out of memory owned by the host, such as a static buffer.
+    ///
+    /// # Safety
+    /// The `len` bytes from `base` must be valid for reads and writes, and must not be used
+    /// for anything else while the region or any block taken from it is alive.
+    pub unsafe fn from_raw_parts(base: NonNull<u8>, len: usize) -> FixedRegion {
+        FixedRegion {
+            base: base.as_ptr() as usize,
+            len,
+            next: Cell::new(0),
+            released: RefCell::new(Vec::new()),
+            owned: None,
+        }
+    }
+
+    /// Bytes of the region not yet handed out, excluding released blocks
+    pub fn remaining(&self) -> usize {
+        self.len - self.next.get()
+    }
+
+    /// Hand out `size` bytes aligned to `align`, reusing a released piece of the same size and
+    /// alignment if there is one
+    fn carve(&self, size: usize, align: usize) -> Result<BlockPtr, BlockError> {
+        let mut released = self.released.borrow_mut();
+
+        let reusable = released.iter().position(|&(offset, piece_size)| {
+            piece_size == size && (self.base + offset) & (align - 1) == 0
+        });
+
+        let offset = match reusable {
+            Some(index) => released.swap_remove(index).0,
+            None => {
+                let start = self.base + self.next.get();
+                let aligned = start.checked_add(align - 1).ok_or(BlockError::OOM)? & !(align - 1);
+                let end = aligned.checked_add(size).ok_or(BlockError::OOM)?;
+
+                if end > self.base + self.len {
+                    return Err(BlockError::OOM);
+                }
+
+                self.next.set(end - self.base);
+                aligned - self.base
+            }
+        };
+
+        unsafe { Ok(NonNull::new_unchecked((self.base + offset) as *mut u8)) }
+    }
+
+    fn release(&self, ptr: BlockPtr, size: usize) {
+        let offset = ptr.as_ptr() as usize - self.base;
+        self.released.borrow_mut().push((offset, size));
+    }
+}
+
+impl BlockSource for FixedRegion {
+    fn alloc_block(&self, size: BlockSize) -> Result<BlockPtr, BlockError> {
+        self.carve(size, size)
+    }
+
+    fn dealloc_block(&self, ptr: BlockPtr, size: BlockSize) {
+        self.release(ptr, size)
+    }
+
+    fn alloc_large(&self, size: usize, align: usize) -> Result<BlockPtr, BlockError> {
+        if !align.is_power_of_two() {
+            return Err(BlockError::BadRequest);
+        }
+
+        self.carve(size, align)
+    }
+
+    fn dealloc_large(&self, ptr: BlockPtr, size: usize, _align: usize) {
+        self.release(ptr, size)
+    }
+}
+
+impl Drop for FixedRegion {
+    fn drop(&mut self) {
+        if let Some(layout) = self.owned {
+            unsafe { dealloc(self.base as *mut u8, layout) }
+        }
+    }
+}
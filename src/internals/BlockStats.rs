@@ -0,0 +1,25 @@
+/// A snapshot of the block-level state of a StickyImmixHeap. Line counts reflect the marks
+/// written by the last collection: lines bump-allocated into since then count as free.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct BlockStats {
+    /// Every block currently owned by the heap
+    pub blocks_in_use: usize,
+    /// Blocks with no live objects, held for reuse
+    pub free_blocks: usize,
+    /// Blocks with live objects and at least one hole
+    pub recyclable_blocks: usize,
+    /// Blocks taken for medium object overflow allocation since the heap was created
+    pub overflow_blocks: usize,
+    /// Blocks taken from the block source since the heap was created
+    pub blocks_acquired: usize,
+    /// Blocks returned to the block source since the heap was created
+    pub blocks_released: usize,
+    /// Lines holding objects that survived the last collection
+    pub lines_marked: usize,
+    /// Lines available for allocation
+    pub lines_free: usize,
+    /// Count of objects in the large object space
+    pub large_objects: usize,
+    /// Bytes allocated to the large object space, including page rounding
+    pub large_object_bytes: usize,
+}
@@ -1,8 +1,10 @@
 use std::intrinsics::size_of;
-use std::ptr::write;
+use std::ptr::{write, write_bytes};
+use std::rc::Rc;
 use crate::internals::Alloc::AllocError;
 use crate::internals::Block::Block;
 use crate::internals::BlockMeta::BlockMeta;
+use crate::internals::BlockSource::BlockSource;
 use crate::internals::constants;
 
 
@@ -14,8 +16,8 @@ pub struct BumpBlock {
 }
 
 impl BumpBlock {
-    pub fn new() -> Result<BumpBlock, AllocError> {
-        let inner_block = Block::new(constants::BLOCK_SIZE)?;
+    pub fn new(source: &Rc<dyn BlockSource>) -> Result<BumpBlock, AllocError> {
+        let inner_block = Block::with_source(constants::BLOCK_SIZE, source.clone())?;
         let block_ptr = inner_block.as_ptr();
 
         let block = BumpBlock {
@@ -58,6 +60,121 @@ impl BumpBlock {
         }
     }
 
+    /// Address of the start of the block
+    pub fn base(&self) -> usize {
+        self.block.as_ptr() as usize
+    }
+
+    /// Size of the hole the cursor is currently bumping into
+    pub fn current_hole_size(&self) -> usize {
+        self.cursor as usize - self.limit as usize
+    }
+
+    /// Clear the line marks ahead of a mark phase
+    pub fn reset_marks(&mut self) {
+        self.meta.reset();
+    }
+
+    /// Flag or unflag the block as selected for evacuation
+    pub fn set_evacuating(&self, evacuating: bool) {
+        self.meta.set_evacuating(evacuating);
+    }
+
+    /// Number of lines holding objects that survived the last collection
+    pub fn marked_lines(&self) -> usize {
+        self.meta.marked_lines()
+    }
+
+    /// Return true if no object in the block survived the last collection
+    pub fn is_free(&self) -> bool {
+        self.meta.is_block_free()
+    }
+
+    /// After a collection, point the cursor and limit at the first hole below the top of the
+    /// block so that allocation can start again from there. Returns false if the block has no
+    /// hole left.
+    pub fn reset_hole(&mut self) -> bool {
+        let block_ptr = self.block.as_ptr();
+
+        match self
+            .meta
+            .find_next_available_hole(constants::BLOCK_CAPACITY, constants::LINE_SIZE)
+        {
+            Some((cursor, limit)) => {
+                self.cursor = unsafe { block_ptr.add(cursor) };
+                self.limit = unsafe { block_ptr.add(limit) };
+                true
+            }
+            None => {
+                self.cursor = block_ptr;
+                self.limit = block_ptr;
+                false
+            }
+        }
+    }
+
+    /// Overwrite every line not marked in the last collection so that reads through dangling
+    /// pointers find garbage that verification recognizes
+    pub fn poison_free_lines(&mut self) {
+        let block_ptr = self.block.as_ptr() as *mut u8;
+
+        for index in 0..constants::USABLE_LINE_COUNT {
+            if !self.meta.is_line_marked(index) {
+                unsafe {
+                    write_bytes(
+                        block_ptr.add(index * constants::LINE_SIZE),
+                        constants::POISON_BYTE,
+                        constants::LINE_SIZE,
+                    )
+                };
+            }
+        }
+    }
+
+    /// Check the cursor, limit and line marks for consistency, returning a description of each
+    /// problem found. Unless `check_marks` is set, marked lines in the current hole are allowed.
+    pub fn verify(&self, check_marks: bool) -> Vec<String> {
+        let mut problems = Vec::new();
+        let base = self.base();
+        let cursor = self.cursor as usize;
+        let limit = self.limit as usize;
+
+        if limit > cursor || limit < base || cursor > base + constants::BLOCK_CAPACITY {
+            problems.push(format!(
+                "cursor {:#x} and limit {:#x} are not a hole within the block",
+                cursor, limit
+            ));
+        }
+
+        let mut any_marked = false;
+        for index in 0..constants::USABLE_LINE_COUNT {
+            match self.meta.line_mark(index) {
+                0 => (),
+                1 => any_marked = true,
+                mark => problems.push(format!("line {} has invalid mark byte {}", index, mark)),
+            }
+        }
+
+        if any_marked && self.meta.is_block_free() {
+            problems.push(String::from("lines are marked but the block is not"));
+        }
+
+        // lines in the current hole were found free by the last sweep and nothing marks them
+        // before the next collection
+        if check_marks && limit < cursor {
+            let first_line = (limit - base) / constants::LINE_SIZE;
+            let last_line = (cursor - base - 1) / constants::LINE_SIZE;
+
+            for index in first_line..=last_line {
+                if self.meta.is_line_marked(index) {
+                    problems.push(format!("line {} is marked but lies in the current hole", index));
+                }
+            }
+        }
+
+        problems
+    }
+
     unsafe fn write<T>(dest: *const u8, object: T) {
         write(dest as *mut T, object);
     }
@@ -80,6 +80,9 @@ impl fmt::Display for RuntimeError {
                 f,
                 "Attempt to modify a container that is already mutably borrowed"
             ),
+            ErrorKind::ForeignHandle => {
+                write!(f, "Handle used with a heap other than the one that created it")
+            }
         }
     }
 }
@@ -119,5 +122,7 @@ pub enum ErrorKind {
     KeyError,
     UnhashableError,
     MutableBorrowError,
+    /// A handle was used with a heap other than the one that created it
+    ForeignHandle,
 
 }
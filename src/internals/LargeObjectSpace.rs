@@ -0,0 +1,117 @@
+use std::collections::HashSet;
+use std::ptr::NonNull;
+use std::rc::Rc;
+use crate::internals::Alloc::{AllocError, AllocHeader};
+use crate::internals::BlockSource::{BlockSource, SystemSource};
+use crate::internals::constants;
+
+/// A single large object allocation. The object header is written at the start of the chunk so
+/// that, as for objects in blocks, the header immediately precedes the object.
+struct LargeObject {
+    ptr: NonNull<u8>,
+    size: usize,
+    /// Where the memory came from and is returned to when the object is freed
+    source: Rc<dyn BlockSource>,
+}
+
+impl LargeObject {
+    /// Base address of every page the object covers
+    fn pages(&self) -> impl Iterator<Item = usize> {
+        let start = self.ptr.as_ptr() as usize;
+        (start..start + self.size).step_by(constants::LARGE_OBJECT_ALIGN)
+    }
+
+    fn free(&self) {
+        self.source
+            .dealloc_large(self.ptr, self.size, constants::LARGE_OBJECT_ALIGN);
+    }
+}
+
+/// Objects too big to fit in a block are each allocated separately, page aligned, from the
+/// heap's block source and tracked here so that they can be freed when unreachable.
+pub struct LargeObjectSpace {
+    objects: Vec<LargeObject>,
+    /// Base addresses of every page covered by an object in the space
+    pages: HashSet<usize>,
+    /// Total size of every object in the space
+    size_bytes: usize,
+    pub source: Rc<dyn BlockSource>,
+}
+
+impl LargeObjectSpace {
+    pub fn new() -> LargeObjectSpace {
+        LargeObjectSpace {
+            objects: Vec::new(),
+            pages: HashSet::new(),
+            size_bytes: 0,
+            source: Rc::new(SystemSource),
+        }
+    }
+
+    /// Allocate a chunk of at least `alloc_size` bytes, header included
+    pub fn alloc(&mut self, alloc_size: usize) -> Result<*const u8, AllocError> {
+        let size = alloc_size
+            .checked_add(constants::LARGE_OBJECT_ALIGN - 1)
+            .ok_or(AllocError::BadRequest)?
+            & !(constants::LARGE_OBJECT_ALIGN - 1);
+
+        let ptr = self
+            .source
+            .alloc_large(size, constants::LARGE_OBJECT_ALIGN)?;
+        let object = LargeObject {
+            ptr,
+            size,
+            source: self.source.clone(),
+        };
+        self.pages.extend(object.pages());
+        self.objects.push(object);
+        self.size_bytes += size;
+
+        Ok(ptr.as_ptr() as *const u8)
+    }
+
+    /// After the mark phase, free every large object whose header was not marked
+    pub fn sweep<H: AllocHeader>(&mut self) {
+        let size_bytes = &mut self.size_bytes;
+        let pages = &mut self.pages;
+
+        self.objects.retain(|object| {
+            let header = unsafe { &*(object.ptr.as_ptr() as *const H) };
+
+            if header.is_marked() {
+                true
+            } else {
+                for page in object.pages() {
+                    pages.remove(&page);
+                }
+                object.free();
+                *size_bytes -= object.size;
+                false
+            }
+        });
+    }
+
+    /// Return true if the address lies within one of the large objects
+    pub fn contains(&self, addr: *const u8) -> bool {
+        self.pages
+            .contains(&(addr as usize & !(constants::LARGE_OBJECT_ALIGN - 1)))
+    }
+
+    /// Count of large objects currently allocated
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    /// Total bytes of every large object currently allocated
+    pub fn size_bytes(&self) -> usize {
+        self.size_bytes
+    }
+}
+
+impl Drop for LargeObjectSpace {
+    fn drop(&mut self) {
+        for object in self.objects.drain(..) {
+            object.free();
+        }
+    }
+}
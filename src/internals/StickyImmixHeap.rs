@@ -1,15 +1,26 @@
-use std::cell::UnsafeCell;
+use std::cell::{Cell, UnsafeCell};
 use std::marker::PhantomData;
 use std::mem::{replace, size_of};
-use std::ptr::{NonNull, write};
+use std::ptr::{copy_nonoverlapping, NonNull, write};
+use std::rc::Rc;
 use crate::frontend::Array::ArraySize;
 use std::slice::from_raw_parts_mut;
 use crate::internals::Alloc::{alloc_size_of, AllocError, AllocHeader, AllocObject, AllocRaw, Mark, RawPtr, SizeClass};
-use crate::internals::BlockList::BlockList;
+use crate::internals::BlockList::{BlockList, ReleasePolicy, TurnoverListener};
+use crate::internals::BlockMeta::BlockMeta;
+use crate::internals::BlockSource::BlockSource;
+use crate::internals::BlockStats::BlockStats;
 use crate::internals::BumpBlock::BumpBlock;
+use crate::internals::LargeObjectSpace::LargeObjectSpace;
+use crate::internals::constants;
 
 pub struct StickyImmixHeap<H> {
     blocks: UnsafeCell<BlockList>,
+    large: UnsafeCell<LargeObjectSpace>,
+    /// The most blocks the heap may hold, or None for no limit
+    max_blocks: Cell<Option<usize>>,
+    /// The most bytes the heap may hold across blocks and large objects, or None for no limit
+    max_bytes: Cell<Option<usize>>,
 
     _header_type: PhantomData<*const H>,
 }
@@ -18,6 +29,9 @@ impl<H> StickyImmixHeap<H> {
     pub fn new() -> StickyImmixHeap<H> {
         StickyImmixHeap {
             blocks: UnsafeCell::new(BlockList::new()),
+            large: UnsafeCell::new(LargeObjectSpace::new()),
+            max_blocks: Cell::new(None),
+            max_bytes: Cell::new(None),
             _header_type: PhantomData,
         }
     }
@@ -28,63 +42,198 @@ impl<H> StickyImmixHeap<H> {
         size_class: SizeClass,
     ) -> Result<*const u8, AllocError> {
         let blocks = unsafe { &mut *self.blocks.get() };
-
+        let large = unsafe { &mut *self.large.get() };
 
         if size_class == SizeClass::Large {
-            return Err(AllocError::BadRequest);
+            if let Some(max_bytes) = self.max_bytes.get() {
+                let heap_size = blocks.len() * constants::BLOCK_SIZE + large.size_bytes();
+                if heap_size + alloc_size > max_bytes {
+                    return Err(AllocError::OOM);
+                }
+            }
+
+            return large.alloc(alloc_size);
         }
 
-        let space = match blocks.head {
-.
-            Some(ref mut head) => {
-                // If this is a medium object that doesn't fit in the hole, use overflow
-                if size_class == SizeClass::Medium && alloc_size > head.current_hole_size() {
-                    return blocks.overflow_alloc(alloc_size);
-                }
+        // the space left by large objects limits the number of blocks
+        let blocks_by_bytes = self
+            .max_bytes
+            .get()
+            .map(|max_bytes| max_bytes.saturating_sub(large.size_bytes()) / constants::BLOCK_SIZE);
+        blocks.block_limit = match (self.max_blocks.get(), blocks_by_bytes) {
+            (Some(max_blocks), Some(by_bytes)) => Some(max_blocks.min(by_bytes)),
+            (max_blocks, by_bytes) => max_blocks.or(by_bytes),
+        };
 
-                // small object that might fit 
-                match head.inner_alloc(alloc_size) {
-                    // the block has a suitable hole
-                    Some(space) => space,
+        if let Some(ref mut head) = blocks.head {
+            // If this is a medium object that doesn't fit in the hole, use overflow
+            if size_class == SizeClass::Medium && alloc_size > head.current_hole_size() {
+                return blocks.overflow_alloc(alloc_size);
+            }
 
-                    // the block does not have a suitable hole
-                    None => {
-                        let previous = replace(head, BumpBlock::new()?);
+            // small object that might fit
+            if let Some(space) = head.inner_alloc(alloc_size) {
+                return Ok(space);
+            }
+        }
+
+        // the head block does not have a suitable hole: retire it and move on to the next
+        // recyclable, free or fresh block
+        if let Some(previous) = blocks.head.take() {
+            blocks.rest.push(previous);
+        }
 
-                        blocks.rest.push(previous);
+        loop {
+            let mut head = blocks.next_block()?;
 
-                        head.inner_alloc(alloc_size).expect("Unexpected error!")
-                    }
+            match head.inner_alloc(alloc_size) {
+                Some(space) => {
+                    blocks.head = Some(head);
+                    return Ok(space);
                 }
+                // a recycled block whose holes are all too small for this object
+                None => blocks.rest.push(head),
             }
+        }
+    }
+}
 
+impl<H: AllocHeader> StickyImmixHeap<H> {
+    /// Set how many entirely free blocks are kept after each collection
+    pub fn set_release_policy(&self, policy: ReleasePolicy) {
+        let blocks = unsafe { &mut *self.blocks.get() };
+        blocks.release_policy = policy;
+    }
 
-            None => {
-                let mut head = BumpBlock::new()?;
-                let space = head
-                    .inner_alloc(alloc_size)
-                    .expect("We expected this object to fit!");
+    /// Limit the size of the heap. Allocations that would take the heap beyond either limit
+    /// fail with `AllocError::OOM`.
+    pub fn set_limits(&self, max_blocks: Option<usize>, max_bytes: Option<usize>) {
+        self.max_blocks.set(max_blocks);
+        self.max_bytes.set(max_bytes);
+    }
 
-                blocks.head = Some(head);
+    /// Allocate blocks and large objects from now on from the given source. Memory already
+    /// held is returned to the source it came from.
+    pub fn set_block_source(&self, source: Rc<dyn BlockSource>) {
+        let blocks = unsafe { &mut *self.blocks.get() };
+        let large = unsafe { &mut *self.large.get() };
+        large.source = source.clone();
+        blocks.source = source;
+    }
 
-                space
-            }
-        } as *const u8;
+    /// Fill lines reclaimed by each sweep with `POISON_BYTE`, to catch use-after-free
+    pub fn set_poison(&self, poison: bool) {
+        let blocks = unsafe { &mut *self.blocks.get() };
+        blocks.poison = poison;
+    }
+
+    /// Check every block for consistency, returning the base address of each block with a
+    /// description of the problem found in it. Unless `check_marks` is set, marked lines in the
+    /// current hole of a block are allowed.
+    pub fn verify_blocks(&self, check_marks: bool) -> Vec<(usize, String)> {
+        let blocks = unsafe { &*self.blocks.get() };
+        blocks.verify(check_marks)
+    }
+
+    /// Return true if the address lies within a block or large object owned by this heap
+    pub fn contains(&self, addr: *const u8) -> bool {
+        let blocks = unsafe { &*self.blocks.get() };
+        let large = unsafe { &*self.large.get() };
+
+        blocks.contains(addr) || large.contains(addr)
+    }
+
+    /// Return a snapshot of block, line and large object counts
+    pub fn stats(&self) -> BlockStats {
+        let blocks = unsafe { &*self.blocks.get() };
+        let large = unsafe { &*self.large.get() };
+
+        BlockStats {
+            large_objects: large.len(),
+            large_object_bytes: large.size_bytes(),
+            ..blocks.stats()
+        }
+    }
 
-        Ok(space)
+    /// Report every block taken from or returned to the block source to the listener, from
+    /// now on
+    pub fn set_turnover_listener(&self, listener: TurnoverListener) {
+        let blocks = unsafe { &mut *self.blocks.get() };
+        blocks.listener = Some(listener);
+    }
+
+    /// Pick sparsely occupied blocks whose objects will be moved out during the next mark phase.
+    /// Must be called before `start_collection()` as it uses the line marks of the last cycle.
+    pub fn select_evacuation_candidates(&self) {
+        let blocks = unsafe { &mut *self.blocks.get() };
+        blocks.select_evacuation_candidates();
+    }
+
+    /// Return true if the object following `header` lies within a block selected for
+    /// evacuation. Large objects are never in a block.
+    pub fn is_evacuating(header: NonNull<H>) -> bool {
+        let header_ref = unsafe { header.as_ref() };
+
+        header_ref.size_class() != SizeClass::Large
+            && BlockMeta::is_evacuating(header.as_ptr() as *const u8)
+    }
+
+    /// Copy the object following `header`, header included, into a free block and return the
+    /// header of the copy. Large objects are never moved. Returns None if no space could be
+    /// found, in which case the object must be marked in place.
+    pub fn copy_object(&self, header: NonNull<H>) -> Option<NonNull<H>> {
+        let header_ref = unsafe { header.as_ref() };
+
+        if header_ref.size_class() == SizeClass::Large {
+            return None;
+        }
+
+        let alloc_size = alloc_size_of(size_of::<H>() + header_ref.size() as usize);
+        let blocks = unsafe { &mut *self.blocks.get() };
+        let space = blocks.evacuation_alloc(alloc_size).ok()?;
+
+        unsafe {
+            copy_nonoverlapping(header.as_ptr() as *const u8, space as *mut u8, alloc_size);
+            Some(NonNull::new_unchecked(space as *mut H))
+        }
+    }
+
+    /// Clear all line marks ahead of a mark phase
+    pub fn start_collection(&self) {
+        let blocks = unsafe { &mut *self.blocks.get() };
+        blocks.reset_marks();
+    }
+
+    /// Mark the lines occupied by the object following `header` so that they are not
+    /// reclaimed. Must be called for every object marked during the mark phase.
+    pub fn mark_lines(header: NonNull<H>) {
+        let header_ref = unsafe { header.as_ref() };
+
+        if header_ref.size_class() != SizeClass::Large {
+            let alloc_size = alloc_size_of(size_of::<H>() + header_ref.size() as usize);
+            BlockMeta::mark_object_lines(header.as_ptr() as *const u8, alloc_size);
+        }
+    }
+
+    /// After the mark phase, reclaim every unmarked line by sorting blocks into free,
+    /// recyclable and full lists, and free every unmarked large object
+    pub fn finish_collection(&self) {
+        let blocks = unsafe { &mut *self.blocks.get() };
+        blocks.sweep();
+
+        let large = unsafe { &mut *self.large.get() };
+        large.sweep::<H>();
     }
 }
 
-impl<H: AllocHeader> AllocRaw for StickyImmixHeap<H> {
-    type Header = H;
-    /// Allocate space for object `T`, creating an header for it and writing the object
-    /// and the header into the space
-    // ANCHOR: DefAlloc
-    fn alloc<T>(&self, object: T) -> Result<RawPtr<T>, AllocError>
+impl<H: AllocHeader> StickyImmixHeap<H> {
+    /// Allocate space for object `T` as `alloc()` does, but hand the object back if there was
+    /// no space for it so that the caller can collect and try again
+    pub fn try_alloc<T>(&self, object: T) -> Result<RawPtr<T>, (AllocError, T)>
         where
-            T: AllocObject<<Self::Header as AllocHeader>::TypeId>,
+            T: AllocObject<H::TypeId>,
     {
-        let header_size = size_of::<Self::Header>();
+        let header_size = size_of::<H>();
         let object_size = size_of::<T>();
         let total_size = header_size + object_size;
 
@@ -93,14 +242,33 @@ impl<H: AllocHeader> AllocRaw for StickyImmixHeap<H> {
         //  If the base allocation address is where the header gets placed, perhaps
         //  this breaks the double-word alignment object alignment desire?
         let alloc_size = alloc_size_of(total_size);
-        let size_class = SizeClass::get_for_size(alloc_size)?;
-        let space = self.find_space(alloc_size, size_class)?;
-        let header = Self::Header::new::<T>(object_size as ArraySize, size_class, Mark::Allocated);
-        unsafe { write(space as *mut Self::Header, header); }
+        let size_class = match SizeClass::get_for_size(alloc_size) {
+            Ok(size_class) => size_class,
+            Err(error) => return Err((error, object)),
+        };
+        let space = match self.find_space(alloc_size, size_class) {
+            Ok(space) => space,
+            Err(error) => return Err((error, object)),
+        };
+        let header = H::new::<T>(object_size as ArraySize, size_class, Mark::Allocated);
+        unsafe { write(space as *mut H, header); }
         let object_space = unsafe { space.offset(header_size as isize) };
         unsafe { write(object_space as *mut T, object); }
         Ok(RawPtr::new(object_space as *const T))
     }
+}
+
+impl<H: AllocHeader> AllocRaw for StickyImmixHeap<H> {
+    type Header = H;
+    /// Allocate space for object `T`, creating an header for it and writing the object
+    /// and the header into the space
+    // ANCHOR: DefAlloc
+    fn alloc<T>(&self, object: T) -> Result<RawPtr<T>, AllocError>
+        where
+            T: AllocObject<<Self::Header as AllocHeader>::TypeId>,
+    {
+        self.try_alloc(object).map_err(|(error, _)| error)
+    }
     /// Allocate space for an array, creating an header for it, writing the header into the space
     /// and returning a pointer to the array space
     // ANCHOR: DefAllocArray
@@ -19,6 +19,9 @@ pub const BLOCK_CAPACITY: usize = BLOCK_SIZE - LINE_COUNT;
 // The first line-mark offset into the block is here.
 pub const LINE_MARK_START: usize = BLOCK_CAPACITY;
 
+// How many lines of a block can hold objects; the remainder hold the line mark bytes
+pub const USABLE_LINE_COUNT: usize = BLOCK_CAPACITY / LINE_SIZE;
+
 // Allocation alignment
 pub const ALLOC_ALIGN_BYTES: usize = 16;
 pub const ALLOC_ALIGN_MASK: usize = !(ALLOC_ALIGN_BYTES - 1);
@@ -31,3 +34,30 @@ pub const MEDIUM_OBJECT_MIN: usize = SMALL_OBJECT_MAX + 1;
 pub const MEDIUM_OBJECT_MAX: usize = BLOCK_CAPACITY;
 pub const LARGE_OBJECT_MIN: usize = MEDIUM_OBJECT_MAX + 1;
 pub const LARGE_OBJECT_MAX: usize = MAX_ALLOC_SIZE;
+
+// Large objects are allocated individually, rounded up to and aligned on page boundaries
+pub const LARGE_OBJECT_ALIGN: usize = 4096;
+
+// How many entirely free blocks are kept for reuse after a collection by default
+pub const DEFAULT_FREE_BLOCKS_RETAINED: usize = 8;
+
+// Recyclable blocks with fewer live lines than this are evacuated during full collections
+pub const EVACUATION_MAX_LIVE_LINES: usize = LINE_COUNT / 4;
+
+// How many fresh blocks evacuation may allocate beyond the free blocks already held
+pub const EVACUATION_HEADROOM_BLOCKS: usize = 4;
+
+// By default, collect at a safepoint after this many bytes have been allocated
+pub const DEFAULT_COLLECT_AFTER_BYTES: usize = 4 * 1024 * 1024;
+
+// By default, every this many safepoint collections is a full collection
+pub const DEFAULT_FULL_COLLECTION_EVERY: usize = 8;
+
+// By default, marking runs on the collecting thread only
+pub const DEFAULT_MARK_THREADS: usize = 1;
+
+// By default, an incremental marking step scans this many objects
+pub const DEFAULT_MARK_BUDGET: usize = 1024;
+
+// Reclaimed lines are filled with this byte in debug mode
+pub const POISON_BYTE: u8 = 0xdb;
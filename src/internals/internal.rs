@@ -1,4 +1,4 @@
-use std::alloc::{alloc, Layout};
+use std::alloc::{alloc, dealloc, Layout};
 use std::ptr::NonNull;
 use crate::internals::Block::{BlockError, BlockPtr, BlockSize};
 
@@ -13,4 +13,13 @@ pub fn alloc_block(size: BlockSize) -> Result<BlockPtr, BlockError> {
             Ok(NonNull::new_unchecked(ptr))
         }
     }
-}
\ No newline at end of file
+}
+
+/// Free a block allocated by `alloc_block`. The size must be the same as was requested.
+pub fn dealloc_block(ptr: BlockPtr, size: BlockSize) {
+    unsafe {
+        let layout = Layout::from_size_align_unchecked(size, size);
+
+        dealloc(ptr.as_ptr(), layout);
+    }
+}
@@ -1,8 +1,11 @@
 pub mod Block;
+pub mod BlockSource;
 pub mod BumpBlock;
 pub mod BlockMeta;
 pub mod constants;
 pub mod BlockList;
+pub mod BlockStats;
+pub mod LargeObjectSpace;
 pub mod StickyImmixHeap;
 pub mod Alloc;
 pub mod internal;